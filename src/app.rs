@@ -4,16 +4,18 @@ use log::{debug, info};
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 
-use crate::drivers::GracefulShutdown;
+use crate::drivers::{DriverStates, GracefulShutdown, ShutdownSender};
 use crate::protocols::v1::ProtocolV1;
 use crate::protocols::Protocols;
 use crate::storage::{AppConfig, Files};
-use crate::user::{Users, UsersManager};
+use crate::user::{LoginThrottle, Users, UsersManager};
 use tokio::sync::Notify;
 
 pub struct Resources {
     pub app_config: AppConfig,
-    pub users: Users,
+    pub users: Arc<Users>,
+    pub login_throttle: LoginThrottle,
+    pub driver_states: DriverStates,
     pub cancel_token: Arc<Notify>,
     pub protocols: Protocols,
     pub protocol_v1: Arc<ProtocolV1>,
@@ -22,27 +24,38 @@ pub struct Resources {
 
 pub type AppResources = Arc<Resources>;
 
-async fn init_app_res() -> anyhow::Result<AppResources> {
+async fn init_app_res(shutdown_sender: ShutdownSender) -> anyhow::Result<AppResources> {
     let config = AppConfig::load();
     debug!(
         "config loaded: {}",
         serde_json::to_string_pretty(&config).unwrap()
     );
 
-    let files = Files::new(config.protocols.clone());
-    let protocol_v1 = Arc::new(ProtocolV1::new(files)); // v1 protocol resources
-    let protocols = Protocols::combine(config.protocols.enabled.as_ref());
-
-    let users = Users::build("users.db").await?;
+    let users = Arc::new(Users::build("users.db", config.auth.clone()).await?);
     users.fix_admin().await?;
     debug!(
         "users loaded: {:?}",
         Vec::from_iter(users.get_users().await?.keys())
     );
+    let driver_states = DriverStates::new(&config.drivers.enabled);
+
+    let files = Files::new(config.protocols.clone());
+    let protocol_v1 = Arc::new(ProtocolV1::new(
+        config.protocols.v1.clone(),
+        config.protocols.enabled.clone(),
+        config.java.clone(),
+        files,
+        users.clone(),
+        driver_states.clone(),
+        shutdown_sender,
+    )); // v1 protocol resources
+    let protocols = Protocols::combine(config.protocols.enabled.as_ref());
 
     let resources = Resources {
         app_config: config,
         users,
+        login_throttle: LoginThrottle::default(),
+        driver_states,
         protocol_v1,
         protocols,
         ws_handlers: Mutex::new(vec![]),
@@ -52,8 +65,8 @@ async fn init_app_res() -> anyhow::Result<AppResources> {
 }
 
 pub async fn run_app() -> anyhow::Result<()> {
-    let resources = init_app_res().await?;
     let mut gs = GracefulShutdown::new();
+    let resources = init_app_res(gs.shutdown_sender()).await?;
 
     resources
         .app_config
@@ -62,7 +75,24 @@ pub async fn run_app() -> anyhow::Result<()> {
         .iter()
         .for_each(|driver_type| gs.add_driver(driver_type.new_driver(resources.clone())));
 
-    gs.watch().await;
+    let request = gs.watch().await;
+    // drivers are stopped, nothing issues new queries
+    resources.users.shutdown().await?;
+    if request.restart {
+        drop(resources);
+        restart_daemon()?;
+    }
     info!("Bye.");
     Ok(())
 }
+
+#[cfg(feature = "self_restart")]
+fn restart_daemon() -> anyhow::Result<()> {
+    info!("Restarting daemon...");
+    crate::utils::restart()
+}
+
+#[cfg(not(feature = "self_restart"))]
+fn restart_daemon() -> anyhow::Result<()> {
+    anyhow::bail!("daemon restart is not supported in this build")
+}
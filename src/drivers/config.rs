@@ -22,12 +22,21 @@ impl Default for DriversConfig {
     }
 }
 
-use std::net::{IpAddr, Ipv4Addr};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tokio::net::TcpListener;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UniDriverConfig {
     pub port: u16,
     pub host: IpAddr,
+    /// pending connections queued by the os before they are accepted
+    #[serde(default = "default_backlog")]
+    pub backlog: u32,
+}
+
+fn default_backlog() -> u32 {
+    1024
 }
 
 impl Default for UniDriverConfig {
@@ -35,6 +44,53 @@ impl Default for UniDriverConfig {
         Self {
             host: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
             port: 11452,
+            backlog: default_backlog(),
         }
     }
 }
+
+impl UniDriverConfig {
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.host, self.port)
+    }
+
+    /// bind a listener that can reuse a port still in TIME_WAIT, so restarts bind immediately
+    pub fn bind(&self) -> std::io::Result<TcpListener> {
+        let addr = self.addr();
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        // on windows SO_REUSEADDR lets another socket take over a port in use,
+        // TIME_WAIT does not block binding there anyway
+        #[cfg(not(windows))]
+        socket.set_reuse_address(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(self.backlog.min(i32::MAX as u32) as i32)?;
+        TcpListener::from_std(socket.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn port_can_be_rebound_right_away() {
+        let mut config = UniDriverConfig {
+            port: 0,
+            ..Default::default()
+        };
+        let listener = config.bind().unwrap();
+        config.port = listener.local_addr().unwrap().port();
+
+        // closing the accepted side first leaves the port in TIME_WAIT
+        let client = TcpStream::connect(config.addr()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        drop(server);
+        drop(client);
+        drop(listener);
+
+        let listener = config.bind().unwrap();
+        assert_eq!(listener.local_addr().unwrap(), config.addr());
+    }
+}
@@ -1,41 +1,162 @@
-use log::debug;
+use log::{debug, info, warn};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::task::JoinSet;
 
 use super::driver::{Driver, StopToken};
 use std::sync::Arc;
+use std::time::Duration;
+
+/// a request to shut the daemon down, sent by actions (e.g. `shutdown_daemon`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ShutdownRequest {
+    /// max time to wait for drivers to finish, `None` waits forever
+    pub timeout: Option<Duration>,
+    /// re-exec the daemon after shutdown
+    pub restart: bool,
+}
+
+pub type ShutdownSender = UnboundedSender<ShutdownRequest>;
+
+/// owns the drivers and their stop tokens, stops them all together
 pub struct GracefulShutdown {
     drivers: Vec<Arc<dyn Driver>>,
+    stop_tokens: Vec<StopToken>,
+    running: JoinSet<()>,
+    shutdown_tx: ShutdownSender,
+    shutdown_rx: UnboundedReceiver<ShutdownRequest>,
 }
 
 impl GracefulShutdown {
     pub fn new() -> Self {
-        Self { drivers: vec![] }
+        let (shutdown_tx, shutdown_rx) = unbounded_channel();
+        Self {
+            drivers: vec![],
+            stop_tokens: vec![],
+            running: JoinSet::new(),
+            shutdown_tx,
+            shutdown_rx,
+        }
     }
 }
 
 impl GracefulShutdown {
     pub fn add_driver(&mut self, driver: impl Driver + 'static) {
+        self.stop_tokens.push(driver.stop_token());
         self.drivers.push(Arc::new(driver));
     }
 
-    pub async fn watch(mut self) {
-        let tokens: Vec<StopToken> = self.drivers.iter().map(|d| d.stop_token()).collect();
-        let shutdown = async move {
-            tokio::signal::ctrl_c()
-                .await
-                .expect("graceful shutdown can't install ctrl+c signal handler");
-            tokens.into_iter().for_each(|t| t.notify_one());
-        };
+    pub fn shutdown_sender(&self) -> ShutdownSender {
+        self.shutdown_tx.clone()
+    }
 
-        let mut join_set = JoinSet::new();
+    /// spawn the `run` futures of all drivers added since the last call
+    pub fn start(&mut self) {
         for driver in self.drivers.drain(..) {
-            join_set.spawn(async move {
+            self.running.spawn(async move {
                 driver.run().await;
             });
         }
+    }
+
+    /// notify every driver to stop and wait for all of them to finish,
+    /// drivers still running after `deadline` are aborted. returns whether all stopped in time
+    pub async fn shutdown(&mut self, deadline: Option<Duration>) -> bool {
+        self.stop_tokens.iter().for_each(|t| t.notify_one());
+
+        let join_all = async { while self.running.join_next().await.is_some() {} };
+        match deadline {
+            Some(deadline) => {
+                if tokio::time::timeout(deadline, join_all).await.is_err() {
+                    warn!("drivers did not stop within {:?}, aborting", deadline);
+                    self.running.abort_all();
+                    return false;
+                }
+            }
+            None => join_all.await,
+        }
+        true
+    }
+
+    /// run all drivers until a shutdown is requested, returns the request that stopped them
+    pub async fn watch(mut self) -> ShutdownRequest {
+        self.start();
 
-        join_set.spawn(shutdown);
         debug!("graceful shutdown start watching");
-        join_set.join_all().await;
+        let request = tokio::select! {
+            res = tokio::signal::ctrl_c() => {
+                res.expect("graceful shutdown can't install ctrl+c signal handler");
+                ShutdownRequest::default()
+            }
+            Some(request) = self.shutdown_rx.recv() => request,
+        };
+        info!("shutdown requested: {:?}", request);
+        self.shutdown(request.timeout).await;
+        request
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::Drivers;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::sync::Notify;
+
+    struct MockDriver {
+        stop_token: StopToken,
+        stopped: Arc<AtomicBool>,
+    }
+
+    #[async_trait::async_trait]
+    impl Driver for MockDriver {
+        async fn run(&self) -> () {
+            self.stop_token.notified().await;
+            self.stopped.store(true, Ordering::SeqCst);
+        }
+
+        fn stop_token(&self) -> StopToken {
+            self.stop_token.clone()
+        }
+
+        fn get_driver_type(&self) -> Drivers {
+            Drivers::Websocket
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_request_stops_drivers() {
+        let mut gs = GracefulShutdown::new();
+        let stopped = Arc::new(AtomicBool::new(false));
+        gs.add_driver(MockDriver {
+            stop_token: Arc::new(Notify::new()),
+            stopped: stopped.clone(),
+        });
+
+        gs.shutdown_sender()
+            .send(ShutdownRequest {
+                timeout: Some(Duration::from_secs(1)),
+                restart: false,
+            })
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(2), gs.watch())
+            .await
+            .unwrap();
+        assert!(stopped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_all_drivers() {
+        let mut gs = GracefulShutdown::new();
+        let stopped = [(); 2].map(|_| Arc::new(AtomicBool::new(false)));
+        for stopped in &stopped {
+            gs.add_driver(MockDriver {
+                stop_token: Arc::new(Notify::new()),
+                stopped: stopped.clone(),
+            });
+        }
+
+        gs.start();
+        assert!(gs.shutdown(Some(Duration::from_secs(1))).await);
+        assert!(stopped.iter().all(|s| s.load(Ordering::SeqCst)));
     }
 }
@@ -6,12 +6,13 @@ pub mod websocket;
 use crate::app::AppResources;
 use crate::drivers::websocket::WsDriver;
 pub use driver::Driver;
-pub use graceful_shutdown::GracefulShutdown;
+pub use graceful_shutdown::{GracefulShutdown, ShutdownRequest, ShutdownSender};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 pub use config::{DriversConfig, UniDriverConfig};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "snake_case")]
 pub enum Drivers {
     Websocket,
@@ -26,3 +27,38 @@ impl Drivers {
         }
     }
 }
+
+/// whether each enabled driver has bound its listener
+#[derive(Clone, Default)]
+pub struct DriverStates(Arc<scc::HashMap<Drivers, bool>>);
+
+impl DriverStates {
+    pub fn new(enabled: &[Drivers]) -> Self {
+        let states = scc::HashMap::new();
+        enabled.iter().for_each(|driver| {
+            let _ = states.insert(driver.clone(), false);
+        });
+        Self(Arc::new(states))
+    }
+
+    pub fn set_bound(&self, driver: Drivers, bound: bool) {
+        self.0.upsert(driver, bound);
+    }
+
+    pub fn enabled(&self) -> Vec<Drivers> {
+        let mut enabled = vec![];
+        self.0.scan(|driver, _| enabled.push(driver.clone()));
+        enabled
+    }
+
+    /// enabled drivers which are not listening
+    pub fn unbound(&self) -> Vec<Drivers> {
+        let mut unbound = vec![];
+        self.0.scan(|driver, bound| {
+            if !bound {
+                unbound.push(driver.clone());
+            }
+        });
+        unbound
+    }
+}
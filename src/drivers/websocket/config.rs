@@ -1,8 +1,153 @@
 use super::super::UniDriverConfig;
 use serde::{Deserialize, Serialize};
+use socket2::{SockRef, TcpKeepalive};
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+use crate::user::User;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct WsDriverConfig {
     #[serde(flatten)]
     pub uni_config: UniDriverConfig,
+    #[serde(default)]
+    pub keepalive: TcpKeepaliveConfig,
+    #[serde(default)]
+    pub request_log: RequestLogConfig,
+    /// refuse new connections of non-admin users, admins can still connect
+    #[serde(default)]
+    pub maintenance: bool,
+    #[serde(default)]
+    pub messages: RefusalMessages,
+}
+
+impl WsDriverConfig {
+    /// whether `user` may not connect right now
+    pub fn refuses(&self, user: &User) -> bool {
+        self.maintenance && !user.is_admin()
+    }
+}
+
+/// bodies of the responses refusing a login or a connection, shown to users by clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RefusalMessages {
+    /// wrong credentials, or a missing, invalid or expired token
+    pub unauthorized: String,
+    /// too many failed logins
+    pub rate_limited: String,
+    pub maintenance: String,
+}
+
+impl Default for RefusalMessages {
+    fn default() -> Self {
+        Self {
+            unauthorized: "invalid credentials or token, log in again".to_string(),
+            rate_limited: "too many failed logins, retry later".to_string(),
+            maintenance: "the daemon is under maintenance, retry later".to_string(),
+        }
+    }
+}
+
+/// request bodies are only logged at trace level, redacted and truncated
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequestLogConfig {
+    /// longest body logged, in chars
+    pub max_body_len: usize,
+}
+
+impl Default for RequestLogConfig {
+    fn default() -> Self {
+        Self { max_body_len: 1024 }
+    }
+}
+
+/// tcp keepalive of accepted connections, lets the os drop half-open connections
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TcpKeepaliveConfig {
+    pub enabled: bool,
+    /// idle time before the first probe
+    pub time_secs: u64,
+    /// time between probes
+    pub interval_secs: u64,
+    /// unanswered probes before the connection is dropped
+    pub retries: u32,
+}
+
+impl Default for TcpKeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            time_secs: 60,
+            interval_secs: 10,
+            retries: 5,
+        }
+    }
+}
+
+impl TcpKeepaliveConfig {
+    pub fn apply(&self, stream: &TcpStream) -> std::io::Result<()> {
+        let socket = SockRef::from(stream);
+        if !self.enabled {
+            return socket.set_keepalive(false);
+        }
+        let keepalive = TcpKeepalive::new()
+            .with_time(Duration::from_secs(self.time_secs))
+            .with_interval(Duration::from_secs(self.interval_secs))
+            .with_retries(self.retries);
+        socket.set_tcp_keepalive(&keepalive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn maintenance_admits_only_admins() {
+        use crate::user::userdb::PermissionGroup;
+
+        let user = User::test;
+        let mut config = WsDriverConfig::default();
+        assert!(!config.refuses(&user(PermissionGroup::User)));
+
+        config.maintenance = true;
+        assert!(config.refuses(&user(PermissionGroup::User)));
+        assert!(!config.refuses(&user(PermissionGroup::Admin)));
+    }
+
+    #[tokio::test]
+    async fn keepalive_applied_to_accepted_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        let config = TcpKeepaliveConfig {
+            time_secs: 30,
+            interval_secs: 5,
+            retries: 3,
+            ..Default::default()
+        };
+        config.apply(&stream).unwrap();
+
+        let socket = SockRef::from(&stream);
+        assert!(socket.keepalive().unwrap());
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(
+                socket.tcp_keepalive_time().unwrap(),
+                Duration::from_secs(30)
+            );
+            assert_eq!(
+                socket.tcp_keepalive_interval().unwrap(),
+                Duration::from_secs(5)
+            );
+            assert_eq!(socket.tcp_keepalive_retries().unwrap(), 3);
+        }
+    }
 }
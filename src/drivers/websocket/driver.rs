@@ -1,22 +1,27 @@
 use crate::app::AppResources;
 use crate::drivers::Drivers;
 use hyper::service::service_fn;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use serde::Deserialize;
 use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::net::TcpListener;
+use std::time::{Duration, SystemTime};
 use tokio::sync::Notify;
 
-use hyper::header::{HeaderName, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
+use hyper::header::{
+    HeaderName, CONNECTION, CONTENT_TYPE, RETRY_AFTER, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY,
+    UPGRADE,
+};
 use hyper::http::HeaderValue;
 use hyper::upgrade::Upgraded;
 
 use super::super::{driver::StopToken, Driver};
+use super::config::RefusalMessages;
+use super::redact;
 use super::ws_behavior::WsBehavior;
-use crate::user::UsersManager;
+use crate::protocols::v1::API_VERSION;
+use crate::user::{JwtClaims, User, UsersManager};
 use anyhow::anyhow;
 use hyper::body::{Bytes, Incoming};
 use hyper::{Method, Request, Response, StatusCode};
@@ -41,7 +46,8 @@ struct LoginParams {
     expired: Option<String>,
 }
 
-fn parse_params<T: DeserializeOwned>(query: Option<&str>) -> anyhow::Result<T> {
+/// `max_log_len` bounds the redacted params in the debug log
+fn parse_params<T: DeserializeOwned>(query: Option<&str>, max_log_len: usize) -> anyhow::Result<T> {
     if let Some(q) = query {
         let params: Vec<&str> = q.split('&').collect();
         let mut map = HashMap::new();
@@ -54,7 +60,7 @@ fn parse_params<T: DeserializeOwned>(query: Option<&str>) -> anyhow::Result<T> {
         }
 
         let json = serde_json::to_string(&map)?;
-        info!("params: {}", json);
+        debug!("params: {}", redact::redact_json(&json, max_log_len));
         let rv = serde_json::from_str::<T>(json.as_str())?;
         return Ok(rv);
     }
@@ -62,6 +68,14 @@ fn parse_params<T: DeserializeOwned>(query: Option<&str>) -> anyhow::Result<T> {
     Err(anyhow!("empty query"))
 }
 
+fn messages(app_resources: &AppResources) -> &RefusalMessages {
+    &app_resources
+        .app_config
+        .drivers
+        .websocket_driver_config
+        .messages
+}
+
 async fn login_handler(
     app_resources: AppResources,
     req: Request<Incoming>,
@@ -70,7 +84,13 @@ async fn login_handler(
     let uri = req.uri();
     let query = uri.query();
 
-    let params = parse_params::<LoginParams>(query);
+    let max_log_len = app_resources
+        .app_config
+        .drivers
+        .websocket_driver_config
+        .request_log
+        .max_body_len;
+    let params = parse_params::<LoginParams>(query, max_log_len);
 
     if params.is_err() {
         debug!("{} login failed: invalid query", remote_addr);
@@ -85,9 +105,20 @@ async fn login_handler(
         .expired
         .map(|s| s.parse::<u64>().unwrap())
         .unwrap_or(30);
+    let throttle = &app_resources.login_throttle;
+    if let Err(retry_after) = throttle.check(&params.usr, remote_addr.ip()) {
+        debug!("{} login failed: too many attempts", remote_addr);
+        return Ok(Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(RETRY_AFTER, retry_after.as_secs_f64().ceil() as u64)
+            .body(Body::from(messages(&app_resources).rate_limited.clone()))
+            .unwrap());
+    }
+
     match app_resources.users.auth(&params.usr, &params.pwd).await {
         Some(_) => match app_resources.users.gen_token(&params.usr, expired).await {
             Ok(token) => {
+                throttle.record_success(&params.usr, remote_addr.ip());
                 debug!(
                     "{} login succeeded with username: {}",
                     remote_addr, params.usr
@@ -104,22 +135,37 @@ async fn login_handler(
             }
         },
         None => {
-            let response = "Unauthorized";
+            throttle.record_failure(&params.usr, remote_addr.ip());
             debug!("{} login failed: unauthorized.", remote_addr);
             Ok(Response::builder()
                 .status(StatusCode::UNAUTHORIZED)
-                .body(Body::from(response))
+                .body(Body::from(messages(&app_resources).unauthorized.clone()))
                 .unwrap())
         }
     }
 }
 
+/// public, so only what a client needs to pick an api, details are behind the `get_daemon_info` action
+fn info_handler() -> Response<Body> {
+    let info = serde_json::json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "api_version": API_VERSION,
+    });
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(info.to_string()))
+        .unwrap()
+}
+
 async fn handle_ws_connection(
     app_resources: AppResources,
     ws: WebSocketStream<TokioIo<Upgraded>>,
     addr: SocketAddr,
+    user: User,
+    expires_at: Option<SystemTime>,
 ) {
-    if let Err(e) = WsBehavior::start(ws, app_resources, addr).await {
+    if let Err(e) = WsBehavior::start(ws, app_resources, addr, user, expires_at).await {
         error!("Error occurred when handling WebSocket connection: {}", e);
     }
 }
@@ -156,12 +202,29 @@ async fn ws_handler(
         None
     };
 
-    if user.is_none() {
+    let user = match user {
+        Some(user) => user,
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .body(Body::from(messages(&app_resources).unauthorized.clone()))
+                .unwrap());
+        }
+    };
+    if app_resources
+        .app_config
+        .drivers
+        .websocket_driver_config
+        .refuses(&user)
+    {
+        debug!("{} refused: maintenance mode", remote_addr);
         return Ok(Response::builder()
-            .status(StatusCode::UNAUTHORIZED)
-            .body(Body::from("Unauthorized"))
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(Body::from(messages(&app_resources).maintenance.clone()))
             .unwrap());
     }
+    // the token was just validated
+    let expires_at = token.and_then(JwtClaims::extract_expiry);
     let res = app_resources.clone();
     let handler = tokio::spawn(async move {
         match hyper::upgrade::on(&mut req).await {
@@ -171,6 +234,8 @@ async fn ws_handler(
                     res,
                     WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await,
                     remote_addr,
+                    user,
+                    expires_at,
                 )
                 .await;
             }
@@ -202,6 +267,7 @@ async fn handle_request(
     match (req.method(), req.uri().path()) {
         (&Method::GET, "/api/v1") => ws_handler(app_resources, req, remote_addr).await,
         (&Method::POST, "/login") => login_handler(app_resources, req, remote_addr).await,
+        (&Method::GET, "/info") => Ok(info_handler()),
         (&Method::HEAD, _) => {
             let mut resp = Response::new(Body::default());
             resp.headers_mut().append(
@@ -232,10 +298,18 @@ impl Driver for WsDriver {
             .drivers
             .websocket_driver_config
             .uni_config;
-        let addr = SocketAddr::new(uni_cfg.host, uni_cfg.port);
+        let addr = uni_cfg.addr();
+        let keepalive = &self
+            .resources
+            .app_config
+            .drivers
+            .websocket_driver_config
+            .keepalive;
 
-        let listener = TcpListener::bind(&addr).await.expect("bind failed");
+        let listener = uni_cfg.bind().expect("bind failed");
         info!("Listening on {}", &addr);
+        let driver_states = &self.resources.driver_states;
+        driver_states.set_bound(Drivers::Websocket, true);
         let builder = Builder::new(TokioExecutor::new());
 
         let mut http_handlers = vec![];
@@ -256,6 +330,9 @@ impl Driver for WsDriver {
                         }
                     };
                     info!("incoming connection accepted: {}", peer_addr);
+                    if let Err(e) = keepalive.apply(&stream) {
+                        warn!("could not set tcp keepalive for {}: {}", peer_addr, e);
+                    }
                     let io = TokioIo::new(stream);
                     let app_res = self.resources.clone();
 
@@ -291,6 +368,8 @@ impl Driver for WsDriver {
                 }
             }
         }
+        drop(listener);
+        driver_states.set_bound(Drivers::Websocket, false);
         for handler in http_handlers {
             handler.await.unwrap();
         }
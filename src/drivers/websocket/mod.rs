@@ -1,5 +1,6 @@
 mod config;
 mod driver;
+mod redact;
 mod ws_behavior;
 
 pub use config::WsDriverConfig;
@@ -0,0 +1,72 @@
+use serde_json::Value;
+
+/// fields whose values never end up in logs
+const SENSITIVE_FIELDS: [&str; 6] = ["pwd", "password", "token", "secret", "data", "content"];
+const REDACTED: &str = "***";
+
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::Object(map) => map.iter_mut().for_each(|(k, v)| {
+            if SENSITIVE_FIELDS.contains(&k.as_str()) {
+                *v = Value::String(REDACTED.to_string());
+            } else {
+                redact_value(v);
+            }
+        }),
+        Value::Array(values) => values.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+/// json with sensitive fields masked, cut to `max_len` chars
+pub fn redact_json(raw: &str, max_len: usize) -> String {
+    let Ok(mut value) = serde_json::from_str::<Value>(raw) else {
+        return format!("<non-json, {} bytes>", raw.len());
+    };
+    redact_value(&mut value);
+    let text = value.to_string();
+    match text.char_indices().nth(max_len) {
+        Some((idx, _)) => format!("{}...({} bytes)", &text[..idx], text.len()),
+        None => text,
+    }
+}
+
+/// action name and echo of a request, safe to log at info level
+pub fn request_summary(raw: &str) -> String {
+    let value = serde_json::from_str::<Value>(raw).unwrap_or_default();
+    format!(
+        "action={} echo={} ({} bytes)",
+        value.get("action").and_then(Value::as_str).unwrap_or("?"),
+        value.get("echo").and_then(Value::as_str).unwrap_or("-"),
+        raw.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_password_is_redacted() {
+        let logged = redact_json(r#"{"usr": "admin", "pwd": "hunter2"}"#, 1024);
+        assert!(!logged.contains("hunter2"));
+        assert!(logged.contains("admin"));
+    }
+
+    #[test]
+    fn nested_chunk_data_is_redacted_and_truncated() {
+        let raw = format!(
+            r#"{{"action": "file_upload_chunk", "params": {{"offset": 0, "data": "{}"}}, "echo": "1"}}"#,
+            "x".repeat(4096)
+        );
+        let logged = redact_json(&raw, 1024);
+        assert!(!logged.contains("xxx"));
+        assert!(logged.contains("file_upload_chunk"));
+
+        assert!(redact_json(&raw.replace("data", "path"), 64).ends_with(" bytes)"));
+        assert_eq!(
+            request_summary(&raw),
+            format!("action=file_upload_chunk echo=1 ({} bytes)", raw.len())
+        );
+    }
+}
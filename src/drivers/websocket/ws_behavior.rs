@@ -1,23 +1,26 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 use anyhow::anyhow;
-use futures::{SinkExt, StreamExt, TryFutureExt};
-use hyper::upgrade::Upgraded;
-use hyper_util::rt::TokioIo;
-use log::{debug, info};
+use futures::{Sink, SinkExt, StreamExt, TryFutureExt};
+use log::{debug, info, trace, warn};
 use serde_json::{json, Value};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::select;
 use tokio::sync::mpsc::WeakUnboundedSender;
 use tokio::sync::mpsc::{error::SendError, unbounded_channel, UnboundedSender};
 use tokio::task::JoinError;
 use tokio_tungstenite::tungstenite::{
     protocol::{frame::coding::CloseCode, CloseFrame},
-    Message,
+    Error as WsError, Message,
 };
 use tokio_tungstenite::WebSocketStream;
 
+use super::redact;
 use crate::app::AppResources;
-use crate::protocols::{v1::event::Events, Protocol, Protocols};
+use crate::protocols::{v1::event::Events, ConnectionContext, Protocol, Protocols};
+use crate::user::users::User;
 
 pub struct WsBehavior {
     #[allow(dead_code)]
@@ -28,6 +31,7 @@ pub struct WsBehavior {
 
     sender: UnboundedSender<Message>,
     addr: SocketAddr,
+    ctx: Arc<ConnectionContext>,
 }
 
 impl WsBehavior {
@@ -36,6 +40,7 @@ impl WsBehavior {
         event_sender: UnboundedSender<(Events, Value)>,
         sender: UnboundedSender<Message>,
         addr: SocketAddr,
+        ctx: Arc<ConnectionContext>,
     ) -> WsBehavior {
         // let mut es = event_sender.clone();
         // tokio::spawn(async move {
@@ -52,6 +57,7 @@ impl WsBehavior {
             event_sender,
             sender,
             addr,
+            ctx,
         }
     }
 }
@@ -59,15 +65,36 @@ impl WsBehavior {
     fn handle_text(&self, msg: String) -> anyhow::Result<()> {
         // TODO 实现action
 
-        info!("received text: {}", msg);
+        info!(
+            "received request from {}: {}",
+            self.addr,
+            redact::request_summary(&msg)
+        );
+        if log::log_enabled!(log::Level::Trace) {
+            let max_len = self
+                .app_resources
+                .app_config
+                .drivers
+                .websocket_driver_config
+                .request_log
+                .max_body_len;
+            trace!("request body: {}", redact::redact_json(&msg, max_len));
+        }
 
         let v1 = self.app_resources.protocol_v1.clone();
         let sender = self.sender.downgrade();
         let protocols = self.app_resources.protocols;
+        let ctx = self.ctx.clone();
+
+        // taken before spawning, so the queue follows the arrival order
+        let mut ticket = v1.ordering_key(&msg).map(|key| ctx.order(key));
 
         tokio::spawn(async move {
+            if let Some(ticket) = ticket.as_mut() {
+                ticket.wait().await;
+            }
             if protocols.is_enabled(Protocols::V1) {
-                if let Some(text) = v1.process_text(msg.as_ref()).await {
+                if let Some(text) = v1.process_text(msg.as_ref(), &ctx).await {
                     Self::weak_send(sender, Message::Text(text));
                 }
             }
@@ -89,10 +116,19 @@ impl WsBehavior {
         let v1 = self.app_resources.protocol_v1.clone();
         let sender = self.sender.downgrade();
         let protocols = self.app_resources.protocols;
+        let ctx = self.ctx.clone();
+
+        // binary frames queue with the text frames of the same file
+        let mut ticket = v1
+            .ordering_key(&String::from_utf8_lossy(&msg))
+            .map(|key| ctx.order(key));
 
         tokio::spawn(async move {
+            if let Some(ticket) = ticket.as_mut() {
+                ticket.wait().await;
+            }
             if protocols.is_enabled(Protocols::V1) {
-                if let Some(bin) = v1.process_binary(msg.as_ref()).await {
+                if let Some(bin) = v1.process_binary(msg.as_ref(), &ctx).await {
                     Self::weak_send(sender, Message::Binary(bin));
                 }
             }
@@ -122,32 +158,114 @@ impl WsBehavior {
     }
 
     fn stop(&self) -> anyhow::Result<()> {
+        self.close(CloseCode::Normal, "")
+    }
+
+    /// the token of the connection expired, the client has to log in again
+    fn expire(&self) -> anyhow::Result<()> {
+        self.close(
+            CloseCode::Policy,
+            "token expired, log in again and reconnect",
+        )
+    }
+
+    fn close(&self, code: CloseCode, reason: &'static str) -> anyhow::Result<()> {
         let close_frame = CloseFrame {
-            code: CloseCode::Normal,
-            reason: "".into(),
+            code,
+            reason: reason.into(),
         };
         self.send(Message::Close(Some(close_frame)))?;
         Ok(())
     }
 }
 
-impl WsBehavior {}
+const MAX_SEND_RETRIES: u32 = 3;
+const SEND_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// what to do after a failed send on the outgoing side
+#[derive(Debug, PartialEq, Eq)]
+enum SendFailure {
+    /// the connection is gone, stop the loop
+    Fatal,
+    /// the frame may go through later
+    Retry,
+    /// the frame can never be sent, skip it
+    Drop,
+}
+
+impl SendFailure {
+    fn classify(err: &WsError) -> Self {
+        match err {
+            WsError::ConnectionClosed
+            | WsError::AlreadyClosed
+            | WsError::Io(_)
+            | WsError::Protocol(_) => SendFailure::Fatal,
+            WsError::WriteBufferFull(_) => SendFailure::Retry,
+            _ => SendFailure::Drop,
+        }
+    }
+}
 
 impl WsBehavior {
-    pub async fn start(
-        ws: WebSocketStream<TokioIo<Upgraded>>,
+    /// send a frame, only fails if the connection is gone
+    async fn send_outgoing<S>(outgoing: &mut S, msg: Message) -> anyhow::Result<()>
+    where
+        S: Sink<Message, Error = WsError> + Unpin,
+    {
+        let mut retries = 0;
+        loop {
+            let err = match outgoing.send(msg.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            match SendFailure::classify(&err) {
+                SendFailure::Fatal => return Err(err.into()),
+                SendFailure::Retry if retries < MAX_SEND_RETRIES => {
+                    retries += 1;
+                    debug!("transient send error, retry {}: {}", retries, err);
+                    tokio::time::sleep(SEND_RETRY_DELAY * retries).await;
+                }
+                _ => {
+                    warn!("dropping outgoing message: {}", err);
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl WsBehavior {
+    pub async fn start<S>(
+        ws: WebSocketStream<S>,
         app_resources: AppResources,
         peer_addr: SocketAddr,
-    ) -> anyhow::Result<()> {
+        user: User,
+        expires_at: Option<SystemTime>,
+    ) -> anyhow::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
         let (mut outgoing, mut incoming) = ws.split();
 
         let (outgoing_tx, mut outgoing_rx) = unbounded_channel();
 
         let (event_tx, mut event_rx) = unbounded_channel();
 
-        let ws_behavior = WsBehavior::new(app_resources.clone(), event_tx, outgoing_tx, peer_addr);
+        let ctx = Arc::new(
+            ConnectionContext::new(user)
+                .with_events(&event_tx)
+                .with_expiry(expires_at),
+        );
+        let ws_behavior = WsBehavior::new(
+            app_resources.clone(),
+            event_tx,
+            outgoing_tx,
+            peer_addr,
+            ctx.clone(),
+        );
 
         let cancel_token = app_resources.cancel_token.clone();
+        let expiry_ctx = ctx.clone();
 
         let incoming_loop_func = async move {
             loop {
@@ -170,6 +288,12 @@ impl WsBehavior {
                         info!("websocket connection from {} closed", peer_addr);
                         break;
                     }
+
+                    _ = expiry_ctx.expired() => {
+                        ws_behavior.expire()?;
+                        info!("websocket connection from {} closed: token expired", peer_addr);
+                        break;
+                    }
                 }
             }
             anyhow::Ok(())
@@ -181,10 +305,10 @@ impl WsBehavior {
                     Some(m) = outgoing_rx.recv() => {
                         match m {
                             Message::Close(_)=>{
-                                outgoing.send(m).await?;
+                                Self::send_outgoing(&mut outgoing, m).await?;
                                 outgoing.close().await?;
                             },
-                            _ => outgoing.send(m).await?
+                            _ => Self::send_outgoing(&mut outgoing, m).await?
                         }
                     }
                     Some((event, data)) = event_rx.recv() => {
@@ -193,7 +317,7 @@ impl WsBehavior {
                             "data": data
                         }).to_string();
 
-                        outgoing.send(Message::text(text)).await?;
+                        Self::send_outgoing(&mut outgoing, Message::text(text)).await?;
                     }
                     else => break,
                 }
@@ -204,6 +328,152 @@ impl WsBehavior {
         let incoming_loop = tokio::spawn(incoming_loop_func)
             .map_err(|e: JoinError| anyhow!("incoming task error: {}", e));
 
-        tokio::try_join!(incoming_loop, outgoing_loop).map(|_| ())
+        let result = tokio::try_join!(incoming_loop, outgoing_loop).map(|_| ());
+        // nobody is left to receive the results
+        ctx.cancel_all(None);
+        ctx.unsubscribe_all();
+        app_resources.protocol_v1.close_downloads(&ctx).await;
+        #[cfg(feature = "session_leak_check")]
+        app_resources.protocol_v1.check_session_leaks(&ctx);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// a sink failing with the given errors before accepting messages
+    struct FlakySink {
+        errors: Vec<WsError>,
+        sent: Vec<Message>,
+    }
+
+    impl Sink<Message> for FlakySink {
+        type Error = WsError;
+
+        fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), WsError> {
+            if self.errors.is_empty() {
+                self.sent.push(item);
+                Ok(())
+            } else {
+                Err(self.errors.remove(0))
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn transient_send_error_recovers() {
+        let mut sink = FlakySink {
+            errors: vec![WsError::WriteBufferFull(Message::text("a"))],
+            sent: vec![],
+        };
+        WsBehavior::send_outgoing(&mut sink, Message::text("a"))
+            .await
+            .unwrap();
+        assert_eq!(sink.sent, vec![Message::text("a")]);
+
+        // the connection keeps working afterwards
+        WsBehavior::send_outgoing(&mut sink, Message::text("b"))
+            .await
+            .unwrap();
+        assert_eq!(sink.sent.len(), 2);
+    }
+
+    async fn resources() -> AppResources {
+        use crate::app::Resources;
+        use crate::drivers::DriverStates;
+        use crate::protocols::v1::ProtocolV1;
+        use crate::storage::{AppConfig, Files};
+        use crate::user::{LoginThrottle, Users};
+        use tokio::sync::{Mutex, Notify};
+
+        let config = AppConfig::default();
+        let users = Arc::new(Users::build(":memory:", config.auth.clone()).await.unwrap());
+        let (shutdown_tx, _) = unbounded_channel();
+        let protocol_v1 = Arc::new(ProtocolV1::new(
+            config.protocols.v1.clone(),
+            config.protocols.enabled.clone(),
+            config.java.clone(),
+            Files::new(config.protocols.clone()),
+            users.clone(),
+            DriverStates::default(),
+            shutdown_tx,
+        ));
+        Arc::new(Resources {
+            protocols: Protocols::combine(&config.protocols.enabled),
+            app_config: config,
+            users,
+            login_throttle: LoginThrottle::default(),
+            driver_states: DriverStates::default(),
+            cancel_token: Arc::new(Notify::new()),
+            protocol_v1,
+            ws_handlers: Mutex::new(vec![]),
+        })
+    }
+
+    #[tokio::test]
+    async fn connection_is_closed_when_token_expires() {
+        use crate::user::userdb::PermissionGroup;
+        use tokio_tungstenite::tungstenite::protocol::Role;
+
+        let (server, client) = tokio::io::duplex(1 << 16);
+        let server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+        let mut client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+        let user = User::test(PermissionGroup::User);
+        let expires_at = SystemTime::now() + Duration::from_millis(200);
+        let connection = tokio::spawn(WsBehavior::start(
+            server,
+            resources().await,
+            "127.0.0.1:0".parse().unwrap(),
+            user,
+            Some(expires_at),
+        ));
+
+        let msg = tokio::time::timeout(Duration::from_secs(5), client.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(SystemTime::now() >= expires_at);
+        match msg {
+            Message::Close(Some(frame)) => {
+                assert_eq!(frame.code, CloseCode::Policy);
+                assert!(frame.reason.contains("expired"));
+            }
+            msg => panic!("expected a close frame, got {:?}", msg),
+        }
+        // let the handshake finish
+        while client.next().await.is_some() {}
+        tokio::time::timeout(Duration::from_secs(5), connection)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn closed_connection_is_fatal() {
+        let mut sink = FlakySink {
+            errors: vec![WsError::ConnectionClosed],
+            sent: vec![],
+        };
+        assert!(WsBehavior::send_outgoing(&mut sink, Message::text("a"))
+            .await
+            .is_err());
     }
 }
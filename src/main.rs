@@ -12,7 +12,12 @@ fn init_logger() {
     unsafe {
         std::env::set_var("RUST_LOG", "trace");
     }
-    pretty_env_logger::init();
+    let logger = pretty_env_logger::formatted_builder()
+        .parse_env("RUST_LOG")
+        .build();
+    let max_level = logger.filter();
+    log::set_boxed_logger(Box::new(utils::TraceLogger::new(logger))).unwrap();
+    log::set_max_level(max_level);
 }
 
 // async fn scan_java() -> anyhow::Result<()> {
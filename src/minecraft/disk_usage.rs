@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use scc::HashMap;
+use uuid::Uuid;
+
+use super::inst_config::default_working_directory;
+
+/// walking a world is expensive, repeated requests within this window reuse the result
+const CACHE_DURATION: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub total: u64,
+    /// size of each top level directory, files directly in the root only count in `total`
+    pub breakdown: BTreeMap<String, u64>,
+}
+
+/// disk usage of instances, cached per instance
+#[derive(Default)]
+pub struct DiskUsageCache {
+    entries: HashMap<Uuid, (Instant, DiskUsage), ahash::RandomState>,
+}
+
+impl DiskUsageCache {
+    pub async fn get(&self, inst_id: Uuid) -> anyhow::Result<DiskUsage> {
+        let cached = self
+            .entries
+            .read_async(&inst_id, |_, (at, usage)| {
+                (at.elapsed() < CACHE_DURATION).then(|| usage.clone())
+            })
+            .await
+            .flatten();
+        if let Some(usage) = cached {
+            return Ok(usage);
+        }
+
+        let usage = dir_usage(default_working_directory(inst_id)).await?;
+        self.entries
+            .upsert_async(inst_id, (Instant::now(), usage.clone()))
+            .await;
+        Ok(usage)
+    }
+}
+
+/// total size of the files under `path`, symlinks are not followed
+pub async fn dir_usage(path: PathBuf) -> anyhow::Result<DiskUsage> {
+    tokio::task::spawn_blocking(move || {
+        let mut usage = DiskUsage {
+            total: 0,
+            breakdown: BTreeMap::new(),
+        };
+        for entry in std::fs::read_dir(&path)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                let size = tree_size(&entry.path());
+                usage.total += size;
+                usage
+                    .breakdown
+                    .insert(entry.file_name().to_string_lossy().into_owned(), size);
+            } else {
+                usage.total += metadata.len();
+            }
+        }
+        Ok(usage)
+    })
+    .await?
+}
+
+/// entries that vanish or can't be read while walking are skipped, the server may be running
+fn tree_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| Some((entry.path(), entry.metadata().ok()?)))
+        .map(|(path, metadata)| {
+            if metadata.is_dir() {
+                tree_size(&path)
+            } else {
+                metadata.len()
+            }
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn usage_of_instance_dir() {
+        let dir = crate::utils::temp_path("disk-usage");
+        std::fs::create_dir_all(dir.join("world/region")).unwrap();
+        std::fs::create_dir_all(dir.join("logs")).unwrap();
+        std::fs::write(dir.join("world/level.dat"), [0; 100]).unwrap();
+        std::fs::write(dir.join("world/region/r.0.0.mca"), [0; 4096]).unwrap();
+        std::fs::write(dir.join("logs/latest.log"), [0; 10]).unwrap();
+        std::fs::write(dir.join("server.properties"), [0; 5]).unwrap();
+
+        let usage = dir_usage(dir.clone()).await.unwrap();
+        assert_eq!(usage.total, 4211);
+        assert_eq!(
+            usage.breakdown,
+            BTreeMap::from([("logs".to_string(), 10), ("world".to_string(), 4196)])
+        );
+
+        assert!(dir_usage(dir.join("missing")).await.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
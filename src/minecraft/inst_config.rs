@@ -1,9 +1,11 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::utils::Encoding;
+use anyhow::{anyhow, bail};
+use log::warn;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
 pub enum InstType {
     Vanilla,
@@ -12,6 +14,15 @@ pub enum InstType {
     Spigot,
 }
 
+impl InstType {
+    pub const ALL: [InstType; 4] = [
+        InstType::Vanilla,
+        InstType::Forge,
+        InstType::Fabric,
+        InstType::Spigot,
+    ];
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum TargetType {
@@ -21,6 +32,31 @@ pub enum TargetType {
 
 const FILE_NAME: &'static str = "daemon_instance.json";
 
+/// where instances live unless configured otherwise
+pub const INSTANCES_ROOT: &str = "./daemon/instances";
+
+pub fn default_working_directory(uuid: Uuid) -> PathBuf {
+    format!("{}/{}", INSTANCES_ROOT, uuid).into()
+}
+
+/// ids of the instances living in `root`, e.g. [`INSTANCES_ROOT`]
+pub async fn instance_ids_in<P: AsRef<Path>>(root: P) -> Vec<Uuid> {
+    let mut ids = vec![];
+    let Ok(mut entries) = tokio::fs::read_dir(root).await else {
+        return ids;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let is_dir = entry.file_type().await.is_ok_and(|t| t.is_dir());
+        if let Some(id) = is_dir
+            .then(|| Uuid::parse_str(&entry.file_name().to_string_lossy()).ok())
+            .flatten()
+        {
+            ids.push(id);
+        }
+    }
+    ids
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct InstConfig {
     pub uuid: Uuid,
@@ -33,6 +69,144 @@ pub struct InstConfig {
     pub instance_type: InstType,
     pub target: PathBuf,
     pub target_type: TargetType,
+    /// max heap in MB, becomes `-Xmx` unless `java_args` already sets it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_mb: Option<u32>,
+    /// initial heap in MB, becomes `-Xms` unless `java_args` already sets it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_memory_mb: Option<u32>,
+    /// free-form labels to organize instances by
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// a single label to group instances by, e.g. the servers of one network
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+/// narrows down a listing of instances, unset fields match every instance
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+pub struct InstanceFilter {
+    pub tag: Option<String>,
+    pub group: Option<String>,
+}
+
+impl InstanceFilter {
+    pub fn matches(&self, config: &InstConfig) -> bool {
+        self.tag
+            .as_ref()
+            .is_none_or(|tag| config.tags.contains(tag))
+            && self
+                .group
+                .as_ref()
+                .is_none_or(|group| config.group.as_ref() == Some(group))
+    }
+}
+
+/// an instance as listed to clients, secrets like the rcon password are left out
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct InstanceSummary {
+    pub uuid: Uuid,
+    pub name: String,
+    pub instance_type: InstType,
+    pub tags: Vec<String>,
+    pub group: Option<String>,
+}
+
+impl From<InstConfig> for InstanceSummary {
+    fn from(config: InstConfig) -> Self {
+        Self {
+            uuid: config.uuid,
+            name: config.name,
+            instance_type: config.instance_type,
+            tags: config.tags,
+            group: config.group,
+        }
+    }
+}
+
+/// instances in `root`, e.g. [`INSTANCES_ROOT`], matching `filter`, sorted by name
+pub async fn list_instances_in<P: AsRef<Path>>(
+    root: P,
+    filter: &InstanceFilter,
+) -> Vec<InstanceSummary> {
+    let root = root.as_ref();
+    let mut instances = vec![];
+    for id in instance_ids_in(root).await {
+        let path = root.join(id.to_string()).join(FILE_NAME);
+        // instances still being set up have no config yet
+        let Ok(content) = tokio::fs::read(&path).await else {
+            continue;
+        };
+        match serde_json::from_slice::<InstConfig>(&content) {
+            Ok(config) if filter.matches(&config) => instances.push(config.into()),
+            Ok(_) => {}
+            Err(e) => warn!("skipping instance {}: invalid {}: {}", id, FILE_NAME, e),
+        }
+    }
+    instances.sort_by(|a: &InstanceSummary, b| a.name.cmp(&b.name));
+    instances
+}
+
+/// what starting an instance would actually use, with the derived settings resolved
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct EffectiveConfig {
+    pub java_path: PathBuf,
+    /// including the heap flags derived from the memory settings
+    pub java_args: Vec<String>,
+    pub working_directory: PathBuf,
+    pub target: PathBuf,
+    pub target_type: TargetType,
+    pub input_encoding: Encoding,
+    pub output_encoding: Encoding,
+}
+
+impl From<InstConfig> for EffectiveConfig {
+    fn from(config: InstConfig) -> Self {
+        Self {
+            java_args: config.effective_java_args(),
+            java_path: config.java_path,
+            working_directory: std::path::absolute(&config.working_directory)
+                .unwrap_or(config.working_directory),
+            target: config.target,
+            target_type: config.target_type,
+            input_encoding: config.input_encoding,
+            output_encoding: config.output_encoding,
+        }
+    }
+}
+
+/// effective config of an instance in the default instances directory
+pub async fn effective_config(id: Uuid) -> anyhow::Result<EffectiveConfig> {
+    effective_config_in(INSTANCES_ROOT, id).await
+}
+
+async fn effective_config_in<P: AsRef<Path>>(root: P, id: Uuid) -> anyhow::Result<EffectiveConfig> {
+    let path = root.as_ref().join(id.to_string()).join(FILE_NAME);
+    let content = match tokio::fs::read(&path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => bail!("instance not found: {}", id),
+        Err(e) => return Err(e.into()),
+    };
+    let config = serde_json::from_slice::<InstConfig>(&content)
+        .map_err(|e| anyhow!("invalid {} of instance {}: {}", FILE_NAME, id, e))?;
+    Ok(config.into())
+}
+
+impl InstConfig {
+    /// `java_args` with the heap flags derived from `memory_mb`/`min_memory_mb`,
+    /// flags given explicitly by the user win
+    pub fn effective_java_args(&self) -> Vec<String> {
+        let has_flag = |prefix: &str| self.java_args.iter().any(|arg| arg.starts_with(prefix));
+        let mut args = vec![];
+        if let Some(min_memory_mb) = self.min_memory_mb.filter(|_| !has_flag("-Xms")) {
+            args.push(format!("-Xms{}M", min_memory_mb));
+        }
+        if let Some(memory_mb) = self.memory_mb.filter(|_| !has_flag("-Xmx")) {
+            args.push(format!("-Xmx{}M", memory_mb));
+        }
+        args.extend(self.java_args.iter().cloned());
+        args
+    }
 }
 
 pub struct InstConfigBuilder {
@@ -46,6 +220,10 @@ pub struct InstConfigBuilder {
     instance_type: Option<InstType>,
     target: Option<PathBuf>,
     target_type: Option<TargetType>,
+    memory_mb: Option<u32>,
+    min_memory_mb: Option<u32>,
+    tags: Option<Vec<String>>,
+    group: Option<String>,
 }
 
 #[allow(dead_code)]
@@ -62,6 +240,10 @@ impl InstConfigBuilder {
             instance_type: None,
             target: None,
             target_type: None,
+            memory_mb: None,
+            min_memory_mb: None,
+            tags: None,
+            group: None,
         }
     }
 
@@ -115,6 +297,26 @@ impl InstConfigBuilder {
         self
     }
 
+    pub fn memory_mb(mut self, memory_mb: u32) -> Self {
+        self.memory_mb = Some(memory_mb);
+        self
+    }
+
+    pub fn min_memory_mb(mut self, min_memory_mb: u32) -> Self {
+        self.min_memory_mb = Some(min_memory_mb);
+        self
+    }
+
+    pub fn tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = Some(tags);
+        self
+    }
+
+    pub fn group<S: Into<String>>(mut self, group: S) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+
     pub fn build(self) -> anyhow::Result<InstConfig> {
         let uuid = self.uuid.unwrap_or_else(Uuid::new_v4);
         Ok(InstConfig {
@@ -122,7 +324,7 @@ impl InstConfigBuilder {
             input_encoding: self.input_encoding.unwrap_or(Encoding::UTF8),
             working_directory: self
                 .working_directory
-                .unwrap_or_else(|| format!("./daemon/instances/{}", uuid).into()),
+                .unwrap_or_else(|| default_working_directory(uuid)),
             java_args: self.java_args.unwrap_or_default(),
             java_path: self.java_path.unwrap_or_else(|| "java".into()),
             name: self.name.ok_or(anyhow::anyhow!("name not set"))?,
@@ -134,6 +336,10 @@ impl InstConfigBuilder {
             target_type: self
                 .target_type
                 .ok_or(anyhow::anyhow!("target_type not set"))?,
+            memory_mb: self.memory_mb,
+            min_memory_mb: self.min_memory_mb,
+            tags: self.tags.unwrap_or_default(),
+            group: self.group,
         })
     }
 }
@@ -204,4 +410,137 @@ mod tests {
             serde_json::from_str::<Value>(INST_CONFIG_TEXT).unwrap()
         );
     }
+
+    fn memory_config(java_args: Vec<String>) -> InstConfig {
+        InstConfigBuilder::new()
+            .name("test")
+            .instance_type(InstType::Vanilla)
+            .target("server.jar")
+            .target_type(TargetType::Jar)
+            .java_args(java_args)
+            .memory_mb(4096)
+            .min_memory_mb(1024)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn memory_mb_derives_heap_flags() {
+        let config = memory_config(vec!["-XX:+UseG1GC".to_string()]);
+        assert_eq!(
+            config.effective_java_args(),
+            vec!["-Xms1024M", "-Xmx4096M", "-XX:+UseG1GC"]
+        );
+    }
+
+    #[test]
+    fn explicit_heap_flags_win() {
+        let config = memory_config(vec!["-Xmx2G".to_string()]);
+        assert_eq!(config.effective_java_args(), vec!["-Xms1024M", "-Xmx2G"]);
+    }
+
+    fn grouped_config(name: &str, tags: &[&str], group: Option<&str>) -> InstConfig {
+        let builder = InstConfigBuilder::new()
+            .name(name)
+            .instance_type(InstType::Vanilla)
+            .target("server.jar")
+            .target_type(TargetType::Jar)
+            .tags(tags.iter().map(|tag| tag.to_string()).collect());
+        match group {
+            Some(group) => builder.group(group),
+            None => builder,
+        }
+        .build()
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn instances_are_listed_by_tag() {
+        let root = crate::utils::temp_path("instances");
+        for config in [
+            grouped_config("survival", &["public"], Some("main")),
+            grouped_config("creative", &["public", "modded"], None),
+            grouped_config("test", &[], Some("main")),
+        ] {
+            let dir = root.join(config.uuid.to_string());
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(FILE_NAME), serde_json::to_vec(&config).unwrap()).unwrap();
+        }
+        // no config yet
+        std::fs::create_dir_all(root.join(Uuid::new_v4().to_string())).unwrap();
+
+        let names = |instances: Vec<InstanceSummary>| {
+            instances.into_iter().map(|i| i.name).collect::<Vec<_>>()
+        };
+        let public = InstanceFilter {
+            tag: Some("public".to_string()),
+            group: None,
+        };
+        assert_eq!(
+            names(list_instances_in(&root, &public).await),
+            vec!["creative", "survival"]
+        );
+        let main = InstanceFilter {
+            group: Some("main".to_string()),
+            ..public
+        };
+        assert_eq!(
+            names(list_instances_in(&root, &main).await),
+            vec!["survival"]
+        );
+        assert_eq!(
+            list_instances_in(&root, &InstanceFilter::default())
+                .await
+                .len(),
+            3
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn effective_config_includes_memory_args() {
+        let root = crate::utils::temp_path("instances");
+        let id = Uuid::new_v4();
+        let dir = root.join(id.to_string());
+        let config = serde_json::json!({
+            "uuid": id,
+            "input_encoding": "utf-8",
+            "output_encoding": "utf-8",
+            "working_directory": dir,
+            "java_path": "java",
+            "java_args": ["-XX:+UseG1GC"],
+            "name": "lobby",
+            "instance_type": "spigot",
+            "target": "paper.jar",
+            "target_type": "jar",
+            "memory_mb": 4096,
+            "min_memory_mb": 1024,
+        });
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(FILE_NAME), serde_json::to_vec(&config).unwrap()).unwrap();
+
+        let effective = effective_config_in(&root, id).await.unwrap();
+        assert_eq!(
+            effective.java_args,
+            vec!["-Xms1024M", "-Xmx4096M", "-XX:+UseG1GC"]
+        );
+        assert_eq!(effective.java_path, PathBuf::from("java"));
+        assert!(effective.working_directory.is_absolute());
+        assert!(effective_config_in(&root, Uuid::new_v4()).await.is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn instance_ids_are_listed() {
+        let root = crate::utils::temp_path("instances");
+        let id = Uuid::new_v4();
+        std::fs::create_dir_all(root.join(id.to_string())).unwrap();
+        std::fs::create_dir_all(root.join("not-an-instance")).unwrap();
+        std::fs::write(root.join(Uuid::new_v4().to_string()), b"").unwrap();
+
+        assert_eq!(instance_ids_in(&root).await, vec![id]);
+        assert!(instance_ids_in(root.join("missing")).await.is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -1,5 +1,14 @@
+pub mod disk_usage;
 mod inst_config;
 mod inst_factory;
 mod inst_manager;
 mod inst_status;
 mod instance;
+pub mod rcon;
+pub mod server_icon;
+pub mod server_version;
+
+pub use inst_config::{
+    effective_config, instance_ids_in, list_instances_in, EffectiveConfig, InstType,
+    InstanceFilter, InstanceSummary, INSTANCES_ROOT,
+};
@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use uuid::Uuid;
+
+use super::inst_config::default_working_directory;
+
+const SERVERDATA_RESPONSE_VALUE: i32 = 0;
+const SERVERDATA_EXECCOMMAND: i32 = 2;
+const SERVERDATA_AUTH: i32 = 3;
+const SERVERDATA_AUTH_RESPONSE: i32 = 2;
+
+/// larger packets are rejected instead of allocated
+const MAX_PACKET_LEN: usize = 1 << 20;
+const DEFAULT_RCON_PORT: u16 = 25575;
+const RCON_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq, Eq)]
+struct Packet {
+    id: i32,
+    kind: i32,
+    body: String,
+}
+
+/// rcon settings of an instance, read from its `server.properties`
+#[derive(Debug, PartialEq, Eq)]
+pub struct RconSettings {
+    pub port: u16,
+    pub password: String,
+}
+
+impl RconSettings {
+    pub async fn load_for_instance(inst_id: Uuid) -> anyhow::Result<Self> {
+        Self::load(default_working_directory(inst_id).join("server.properties")).await
+    }
+
+    pub async fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .context("could not read server.properties")?;
+        Self::parse(&content)
+    }
+
+    fn parse(content: &str) -> anyhow::Result<Self> {
+        let properties = parse_properties(content);
+        if properties.get("enable-rcon").map(String::as_str) != Some("true") {
+            bail!("rcon is not enabled");
+        }
+        let port = match properties.get("rcon.port") {
+            Some(port) => port.parse().context("invalid rcon.port")?,
+            None => DEFAULT_RCON_PORT,
+        };
+        let password = properties
+            .get("rcon.password")
+            .filter(|p| !p.is_empty())
+            .ok_or(anyhow!("rcon.password is not set"))?
+            .clone();
+        Ok(Self { port, password })
+    }
+}
+
+/// parse java `.properties` content, enough for `server.properties`
+fn parse_properties(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+        .filter_map(|line| {
+            let idx = line.find(['=', ':'])?;
+            let value = line[idx + 1..]
+                .trim()
+                .replace("\\:", ":")
+                .replace("\\=", "=");
+            Some((line[..idx].trim().to_string(), value))
+        })
+        .collect()
+}
+
+/// run a command on a local instance through its configured rcon
+pub async fn instance_command(inst_id: Uuid, command: &str) -> anyhow::Result<String> {
+    let settings = RconSettings::load_for_instance(inst_id).await?;
+    tokio::time::timeout(RCON_TIMEOUT, async {
+        let mut client =
+            RconClient::connect(("127.0.0.1", settings.port), &settings.password).await?;
+        client.command(command).await
+    })
+    .await
+    .context("rcon timed out")?
+}
+
+/// `say` command broadcasting a message to all players, `say` only takes a single line
+pub fn say_command(message: &str) -> String {
+    let line = message
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("say {}", line)
+}
+
+pub struct RconClient {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+impl RconClient {
+    pub async fn connect<A: ToSocketAddrs>(addr: A, password: &str) -> anyhow::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = Self { stream, next_id: 1 };
+
+        let id = client.send(SERVERDATA_AUTH, password).await?;
+        loop {
+            let packet = client.recv().await?;
+            // some servers send an empty response value before the auth response
+            if packet.kind != SERVERDATA_AUTH_RESPONSE {
+                continue;
+            }
+            if packet.id == -1 {
+                bail!("rcon authentication failed");
+            }
+            if packet.id != id {
+                bail!("unexpected rcon auth response id {}", packet.id);
+            }
+            return Ok(client);
+        }
+    }
+
+    /// run a command, joins responses split over multiple packets
+    pub async fn command(&mut self, command: &str) -> anyhow::Result<String> {
+        let id = self.send(SERVERDATA_EXECCOMMAND, command).await?;
+        // the server answers packets in order, so the reply to this one marks the end of the response
+        let end_id = self.send(SERVERDATA_RESPONSE_VALUE, "").await?;
+
+        let mut response = String::new();
+        loop {
+            let packet = self.recv().await?;
+            if packet.id == end_id {
+                return Ok(response);
+            }
+            if packet.id == id {
+                response.push_str(&packet.body);
+            }
+        }
+    }
+
+    async fn send(&mut self, kind: i32, body: &str) -> anyhow::Result<i32> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut buf = Vec::with_capacity(body.len() + 14);
+        buf.extend_from_slice(&(body.len() as i32 + 10).to_le_bytes());
+        buf.extend_from_slice(&id.to_le_bytes());
+        buf.extend_from_slice(&kind.to_le_bytes());
+        buf.extend_from_slice(body.as_bytes());
+        buf.extend_from_slice(&[0, 0]);
+        self.stream.write_all(&buf).await?;
+        Ok(id)
+    }
+
+    async fn recv(&mut self) -> anyhow::Result<Packet> {
+        let len = self.stream.read_i32_le().await? as usize;
+        if !(10..=MAX_PACKET_LEN).contains(&len) {
+            bail!("invalid rcon packet length {}", len);
+        }
+        let mut buf = vec![0; len];
+        self.stream.read_exact(&mut buf).await?;
+
+        let id = i32::from_le_bytes(buf[0..4].try_into().unwrap());
+        let kind = i32::from_le_bytes(buf[4..8].try_into().unwrap());
+        let body = String::from_utf8_lossy(&buf[8..len - 2]).into_owned();
+        Ok(Packet { id, kind, body })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    async fn read_packet(stream: &mut TcpStream) -> Option<Packet> {
+        let len = stream.read_i32_le().await.ok()? as usize;
+        let mut buf = vec![0; len];
+        stream.read_exact(&mut buf).await.ok()?;
+        Some(Packet {
+            id: i32::from_le_bytes(buf[0..4].try_into().unwrap()),
+            kind: i32::from_le_bytes(buf[4..8].try_into().unwrap()),
+            body: String::from_utf8_lossy(&buf[8..len - 2]).into_owned(),
+        })
+    }
+
+    async fn write_packet(stream: &mut TcpStream, id: i32, kind: i32, body: &str) {
+        let mut buf = vec![];
+        buf.extend_from_slice(&(body.len() as i32 + 10).to_le_bytes());
+        buf.extend_from_slice(&id.to_le_bytes());
+        buf.extend_from_slice(&kind.to_le_bytes());
+        buf.extend_from_slice(body.as_bytes());
+        buf.extend_from_slice(&[0, 0]);
+        stream.write_all(&buf).await.unwrap();
+    }
+
+    /// a minimal rcon server answering `list` in two packets
+    async fn mock_server(password: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while let Some(packet) = read_packet(&mut stream).await {
+                match packet.kind {
+                    SERVERDATA_AUTH => {
+                        write_packet(&mut stream, packet.id, SERVERDATA_RESPONSE_VALUE, "").await;
+                        let id = if packet.body == password {
+                            packet.id
+                        } else {
+                            -1
+                        };
+                        write_packet(&mut stream, id, SERVERDATA_AUTH_RESPONSE, "").await;
+                    }
+                    SERVERDATA_EXECCOMMAND => {
+                        let kind = SERVERDATA_RESPONSE_VALUE;
+                        write_packet(
+                            &mut stream,
+                            packet.id,
+                            kind,
+                            "There are 2 of a max of 20 players online: ",
+                        )
+                        .await;
+                        write_packet(&mut stream, packet.id, kind, "Steve, Alex").await;
+                    }
+                    _ => {
+                        write_packet(
+                            &mut stream,
+                            packet.id,
+                            SERVERDATA_RESPONSE_VALUE,
+                            "Unknown request 0",
+                        )
+                        .await;
+                    }
+                }
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn command_joins_multi_packet_response() {
+        let port = mock_server("secret").await;
+        let mut client = RconClient::connect(("127.0.0.1", port), "secret")
+            .await
+            .unwrap();
+        assert_eq!(
+            client.command("list").await.unwrap(),
+            "There are 2 of a max of 20 players online: Steve, Alex"
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_password_fails_auth() {
+        let port = mock_server("secret").await;
+        assert!(RconClient::connect(("127.0.0.1", port), "wrong")
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn broadcast_uses_single_line_say() {
+        assert_eq!(
+            say_command("Server restarting\n in 5 minutes"),
+            "say Server restarting in 5 minutes"
+        );
+    }
+
+    #[test]
+    fn parse_rcon_settings() {
+        let settings = RconSettings::parse(
+            "#Minecraft server properties\nenable-rcon=true\nrcon.port=25580\nrcon.password=p\\=w\n",
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            RconSettings {
+                port: 25580,
+                password: "p=w".to_string()
+            }
+        );
+
+        assert!(RconSettings::parse("enable-rcon=false\nrcon.password=x").is_err());
+        assert!(RconSettings::parse("enable-rcon=true\nrcon.password=").is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use uuid::Uuid;
+
+use super::inst_config::default_working_directory;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+/// minecraft only shows icons of exactly this size
+const ICON_SIZE: u32 = 64;
+
+fn icon_path(inst_id: Uuid) -> PathBuf {
+    default_working_directory(inst_id).join("server-icon.png")
+}
+
+/// check `png` is a png image of 64x64, by its IHDR header
+pub fn validate(png: &[u8]) -> anyhow::Result<()> {
+    // signature, IHDR length and type, width, height
+    if png.len() < 24 || png[..8] != PNG_SIGNATURE || &png[12..16] != b"IHDR" {
+        bail!("server icon is not a png image");
+    }
+    let width = u32::from_be_bytes(png[16..20].try_into().unwrap());
+    let height = u32::from_be_bytes(png[20..24].try_into().unwrap());
+    if (width, height) != (ICON_SIZE, ICON_SIZE) {
+        bail!(
+            "server icon must be {}x{}, got {}x{}",
+            ICON_SIZE,
+            ICON_SIZE,
+            width,
+            height
+        );
+    }
+    Ok(())
+}
+
+pub async fn load_for_instance(inst_id: Uuid) -> anyhow::Result<Vec<u8>> {
+    load(icon_path(inst_id)).await
+}
+
+pub async fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<u8>> {
+    tokio::fs::read(path)
+        .await
+        .context("could not read server-icon.png")
+}
+
+pub async fn save_for_instance(inst_id: Uuid, png: &[u8]) -> anyhow::Result<()> {
+    save(icon_path(inst_id), png).await
+}
+
+/// validate and write an icon, invalid images never reach the disk
+pub async fn save<P: AsRef<Path>>(path: P, png: &[u8]) -> anyhow::Result<()> {
+    validate(png)?;
+    tokio::fs::write(path, png)
+        .await
+        .context("could not write server-icon.png")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a png header with the given size, enough for validation
+    fn png_header(width: u32, height: u32) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend_from_slice(&13u32.to_be_bytes());
+        png.extend_from_slice(b"IHDR");
+        png.extend_from_slice(&width.to_be_bytes());
+        png.extend_from_slice(&height.to_be_bytes());
+        png.extend_from_slice(&[8, 6, 0, 0, 0, 0, 0, 0, 0]);
+        png
+    }
+
+    #[tokio::test]
+    async fn valid_icon_is_saved() {
+        let path = crate::utils::temp_path("server-icon").with_extension("png");
+        let png = png_header(64, 64);
+
+        save(&path, &png).await.unwrap();
+        assert_eq!(load(&path).await.unwrap(), png);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn wrong_size_or_format_is_rejected() {
+        let path = crate::utils::temp_path("server-icon").with_extension("png");
+
+        let err = save(&path, &png_header(128, 64)).await.unwrap_err();
+        assert!(err.to_string().contains("64x64, got 128x64"));
+        assert!(validate(b"GIF89a not a png at all").is_err());
+        assert!(validate(&png_header(64, 64)[..20]).is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use anyhow::{anyhow, Context};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::inst_config::default_working_directory;
+use crate::utils::read_zip_entry;
+
+/// written by paper next to the server jar
+const VERSION_HISTORY: &str = "version_history.json";
+/// embedded in vanilla server jars, and in those of most forks
+const VERSION_JSON: &str = "version.json";
+const MAX_VERSION_JSON_SIZE: u64 = 1 << 20;
+
+/// e.g. `git-Paper-196 (MC: 1.20.1)`
+static PAPER_VERSION_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(.+) \(MC: (.+)\)$").unwrap());
+
+/// the version of the server actually installed, regardless of what the config declares
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ServerVersion {
+    pub minecraft_version: String,
+    /// build of the server software, if it tells
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build: Option<String>,
+    /// file the version was read from
+    pub source: String,
+}
+
+#[derive(Deserialize)]
+struct VersionHistory {
+    #[serde(rename = "currentVersion")]
+    current_version: String,
+}
+
+#[derive(Deserialize)]
+struct VersionJson {
+    id: String,
+}
+
+pub async fn read_for_instance(inst_id: Uuid) -> anyhow::Result<ServerVersion> {
+    read(default_working_directory(inst_id)).await
+}
+
+/// read the version from paper's `version_history.json` in `dir`,
+/// or from the `version.json` of a server jar in `dir`
+pub async fn read<P: AsRef<Path>>(dir: P) -> anyhow::Result<ServerVersion> {
+    let dir = dir.as_ref().to_path_buf();
+    tokio::task::spawn_blocking(move || read_blocking(&dir)).await?
+}
+
+fn read_blocking(dir: &Path) -> anyhow::Result<ServerVersion> {
+    let history = dir.join(VERSION_HISTORY);
+    if history.is_file() {
+        let history: VersionHistory = serde_json::from_slice(&std::fs::read(&history)?)
+            .with_context(|| format!("invalid {}", VERSION_HISTORY))?;
+        return parse_paper_version(&history.current_version);
+    }
+
+    for jar in server_jars(dir)? {
+        let Some(json) = read_zip_entry(&jar, VERSION_JSON, MAX_VERSION_JSON_SIZE)
+            .with_context(|| format!("could not read {}", jar.display()))?
+        else {
+            continue;
+        };
+        let version: VersionJson = serde_json::from_slice(&json)
+            .with_context(|| format!("invalid {} in {}", VERSION_JSON, jar.display()))?;
+        return Ok(ServerVersion {
+            minecraft_version: version.id,
+            build: None,
+            source: jar
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        });
+    }
+    Err(anyhow!("no server version found in {}", dir.display()))
+}
+
+fn parse_paper_version(current_version: &str) -> anyhow::Result<ServerVersion> {
+    let captures = PAPER_VERSION_REGEX
+        .captures(current_version)
+        .ok_or(anyhow!("unknown server version: {}", current_version))?;
+    Ok(ServerVersion {
+        minecraft_version: captures[2].to_string(),
+        build: Some(captures[1].to_string()),
+        source: VERSION_HISTORY.to_string(),
+    })
+}
+
+/// jars directly in `dir`, `server.jar` first
+fn server_jars(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut jars = std::fs::read_dir(dir)
+        .with_context(|| format!("could not list {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "jar"))
+        .collect::<Vec<_>>();
+    jars.sort_by_key(|path| (!path.ends_with("server.jar"), path.clone()));
+    Ok(jars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    /// a jar holding `name`, deflated
+    fn jar(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        writer
+            .start_file(name, SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = crate::utils::temp_path("mcsl-server-version");
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn version_is_read_from_the_jar() {
+        let dir = temp_dir();
+        std::fs::write(dir.join("libraries.jar"), jar("a.txt", b"a")).unwrap();
+        std::fs::write(
+            dir.join("server.jar"),
+            jar(VERSION_JSON, br#"{"id": "1.20.1", "world_version": 3465}"#),
+        )
+        .unwrap();
+
+        assert_eq!(
+            read(&dir).await.unwrap(),
+            ServerVersion {
+                minecraft_version: "1.20.1".to_string(),
+                build: None,
+                source: "server.jar".to_string(),
+            }
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn paper_version_history_wins() {
+        let dir = temp_dir();
+        std::fs::write(
+            dir.join(VERSION_HISTORY),
+            r#"{"currentVersion": "git-Paper-196 (MC: 1.20.1)"}"#,
+        )
+        .unwrap();
+
+        let version = read(&dir).await.unwrap();
+        assert_eq!(version.minecraft_version, "1.20.1");
+        assert_eq!(version.build.as_deref(), Some("git-Paper-196"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_version_is_an_error() {
+        let dir = temp_dir();
+        assert!(read(&dir).await.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
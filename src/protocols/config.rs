@@ -2,9 +2,12 @@ use super::{v1::ProtocolV1Config, Protocols};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ProtocolConfig {
     pub enabled: Vec<Protocols>,
     pub v1: ProtocolV1Config,
+    /// open upload + download sessions allowed at once, across all files and connections
+    pub max_file_sessions: usize,
 }
 
 impl Default for ProtocolConfig {
@@ -12,6 +15,7 @@ impl Default for ProtocolConfig {
         Self {
             enabled: vec![Protocols::V1],
             v1: ProtocolV1Config::default(),
+            max_file_sessions: 64,
         }
     }
 }
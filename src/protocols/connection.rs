@@ -0,0 +1,300 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use scc::{hash_map::Entry, HashMap, HashSet};
+use serde_json::Value;
+use tokio::sync::{
+    mpsc::{UnboundedSender, WeakUnboundedSender},
+    oneshot,
+};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::protocols::v1::event::Events;
+use crate::user::users::User;
+
+pub type EventSender = UnboundedSender<(Events, Value)>;
+
+/// per-connection state shared by all actions of a connection
+pub struct ConnectionContext {
+    pub user: User,
+    next_task_id: AtomicU64,
+    // use ahash to speed up ops
+    tasks: HashMap<u64, CancellationToken, ahash::RandomState>,
+    // file sessions opened by this connection
+    sessions: HashSet<Uuid, ahash::RandomState>,
+    // completion of the last ordered action of each key
+    ordered: HashMap<Uuid, oneshot::Receiver<()>, ahash::RandomState>,
+    // where events pushed to this connection go, if the driver supports them.
+    // weak, so the connection can still close while subscriptions run
+    events: Option<WeakUnboundedSender<(Events, Value)>>,
+    subscriptions: HashMap<Events, CancellationToken, ahash::RandomState>,
+    // when the credentials of the connection stop being valid
+    expires_at: Option<SystemTime>,
+}
+
+/// a place in the queue of actions sharing a key, see [`ConnectionContext::order`]
+pub struct OrderTicket {
+    key: Uuid,
+    prev: Option<oneshot::Receiver<()>>,
+    // dropping it lets the next action of the key run
+    done: Option<oneshot::Sender<()>>,
+    ctx: Arc<ConnectionContext>,
+}
+
+impl OrderTicket {
+    /// wait until the previous action of the key has finished
+    pub async fn wait(&mut self) {
+        if let Some(prev) = self.prev.take() {
+            let _ = prev.await;
+        }
+    }
+}
+
+impl Drop for OrderTicket {
+    fn drop(&mut self) {
+        self.done.take();
+        // forget the key once its last action is done
+        self.ctx.ordered.remove_if(&self.key, |rx| {
+            matches!(rx.try_recv(), Err(oneshot::error::TryRecvError::Closed))
+        });
+    }
+}
+
+/// an in-flight action of a connection, untracked on drop
+pub struct InFlightTask {
+    pub id: u64,
+    pub token: CancellationToken,
+    ctx: Arc<ConnectionContext>,
+}
+
+impl Drop for InFlightTask {
+    fn drop(&mut self) {
+        self.ctx.tasks.remove(&self.id);
+    }
+}
+
+impl ConnectionContext {
+    pub fn new(user: User) -> Self {
+        Self {
+            user,
+            next_task_id: AtomicU64::new(0),
+            tasks: HashMap::default(),
+            sessions: HashSet::default(),
+            ordered: HashMap::default(),
+            events: None,
+            subscriptions: HashMap::default(),
+            expires_at: None,
+        }
+    }
+
+    pub fn with_expiry(mut self, expires_at: Option<SystemTime>) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    /// resolves once the credentials of the connection have expired, never if they don't
+    pub async fn expired(&self) {
+        match self.expires_at {
+            Some(expires_at) => {
+                let left = expires_at
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default();
+                tokio::time::sleep(left).await
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    pub fn with_events(mut self, events: &EventSender) -> Self {
+        self.events = Some(events.downgrade());
+        self
+    }
+
+    /// `None` once the connection is gone, or if it does not take events
+    pub fn events(&self) -> Option<EventSender> {
+        self.events.as_ref()?.upgrade()
+    }
+
+    /// start a subscription to `event`, replacing the previous one,
+    /// the returned token is cancelled once it ends
+    pub fn subscribe(&self, event: Events) -> CancellationToken {
+        let token = CancellationToken::new();
+        if let Some(prev) = self.subscriptions.upsert(event, token.clone()) {
+            prev.cancel();
+        }
+        token
+    }
+
+    /// returns whether there was a subscription to `event`
+    pub fn unsubscribe(&self, event: Events) -> bool {
+        self.subscriptions
+            .remove(&event)
+            .map(|(_, token)| token.cancel())
+            .is_some()
+    }
+
+    pub fn unsubscribe_all(&self) {
+        self.subscriptions.retain(|_, token| {
+            token.cancel();
+            false
+        });
+    }
+
+    /// register an in-flight action, it can be cancelled through its token
+    pub fn track(self: &Arc<Self>) -> InFlightTask {
+        let id = self.next_task_id.fetch_add(1, Ordering::Relaxed);
+        let token = CancellationToken::new();
+        let _ = self.tasks.insert(id, token.clone());
+        InFlightTask {
+            id,
+            token,
+            ctx: self.clone(),
+        }
+    }
+
+    /// cancel all in-flight actions except `except`, returns how many were cancelled
+    pub fn cancel_all(&self, except: Option<u64>) -> usize {
+        let mut cancelled = 0;
+        self.tasks.retain(|id, token| {
+            if Some(*id) == except {
+                return true;
+            }
+            token.cancel();
+            cancelled += 1;
+            false
+        });
+        cancelled
+    }
+
+    /// actions are spawned as they arrive and may run in any order, those taking a ticket
+    /// of the same key run one after another, in the order the tickets were taken
+    pub fn order(self: &Arc<Self>, key: Uuid) -> OrderTicket {
+        let (done, rx) = oneshot::channel();
+        let prev = match self.ordered.entry(key) {
+            Entry::Occupied(mut entry) => Some(std::mem::replace(entry.get_mut(), rx)),
+            Entry::Vacant(entry) => {
+                entry.insert_entry(rx);
+                None
+            }
+        };
+        OrderTicket {
+            key,
+            prev,
+            done: Some(done),
+            ctx: self.clone(),
+        }
+    }
+
+    pub fn add_session(&self, file_id: Uuid) {
+        let _ = self.sessions.insert(file_id);
+    }
+
+    pub fn sessions(&self) -> Vec<Uuid> {
+        let mut sessions = vec![];
+        self.sessions.scan(|id| sessions.push(*id));
+        sessions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::userdb::PermissionGroup;
+    use std::time::Duration;
+
+    fn context() -> Arc<ConnectionContext> {
+        Arc::new(ConnectionContext::new(User::test(PermissionGroup::User)))
+    }
+
+    #[tokio::test]
+    async fn cancel_all_cancels_in_flight_tasks() {
+        let ctx = context();
+        let handles = (0..3)
+            .map(|_| {
+                let task = ctx.track();
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = task.token.cancelled() => true,
+                        _ = tokio::time::sleep(Duration::from_secs(60)) => false,
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let current = ctx.track();
+
+        assert_eq!(ctx.cancel_all(Some(current.id)), 3);
+        for handle in handles {
+            assert!(tokio::time::timeout(Duration::from_secs(1), handle)
+                .await
+                .unwrap()
+                .unwrap());
+        }
+        assert!(!current.token.is_cancelled());
+        assert_eq!(ctx.tasks.len(), 1);
+
+        drop(current);
+        assert_eq!(ctx.tasks.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn ordered_actions_run_in_ticket_order() {
+        let ctx = context();
+        let file_id = Uuid::new_v4();
+        let applied = Arc::new(std::sync::Mutex::new(vec![]));
+
+        let tasks = (0..4u64)
+            .map(|chunk| {
+                let mut ticket = ctx.order(file_id);
+                let applied = applied.clone();
+                tokio::spawn(async move {
+                    // later chunks would finish first if they were not ordered
+                    tokio::time::sleep(Duration::from_millis(40 - chunk * 10)).await;
+                    ticket.wait().await;
+                    applied.lock().unwrap().push(chunk);
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(*applied.lock().unwrap(), vec![0, 1, 2, 3]);
+        assert!(ctx.ordered.is_empty());
+    }
+
+    #[tokio::test]
+    async fn expiry_resolves_at_the_deadline() {
+        let ctx = context();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), ctx.expired())
+                .await
+                .is_err()
+        );
+
+        let ctx = ConnectionContext::new(ctx.user.clone())
+            .with_expiry(Some(SystemTime::now() + Duration::from_millis(50)));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), ctx.expired())
+                .await
+                .is_err()
+        );
+        tokio::time::timeout(Duration::from_secs(1), ctx.expired())
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn subscribing_again_replaces_the_subscription() {
+        let ctx = context();
+        let first = ctx.subscribe(Events::Tick);
+        let second = ctx.subscribe(Events::Tick);
+        assert!(first.is_cancelled());
+        assert!(!second.is_cancelled());
+
+        assert!(ctx.unsubscribe(Events::Tick));
+        assert!(second.is_cancelled());
+        assert!(!ctx.unsubscribe(Events::Tick));
+    }
+}
@@ -1,12 +1,14 @@
 mod config;
+mod connection;
 mod protocol;
 pub mod v1;
 use serde::{Deserialize, Serialize};
 
 pub use config::ProtocolConfig;
+pub use connection::ConnectionContext;
 pub use protocol::Protocol;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum Protocols {
     V1,
@@ -1,4 +1,10 @@
+use std::sync::Arc;
+
+use super::ConnectionContext;
+
 pub trait Protocol {
-    async fn process_text(&self, raw: &str) -> Option<String>;
-    async fn process_binary(&self, raw: &[u8]) -> Option<Vec<u8>>;
+    async fn process_text(&self, raw: &str, ctx: &Arc<ConnectionContext>) -> Option<String>;
+    async fn process_binary(&self, raw: &[u8], ctx: &Arc<ConnectionContext>) -> Option<Vec<u8>>;
+    /// names of the actions this protocol understands
+    fn supported_actions(&self) -> &[&'static str];
 }
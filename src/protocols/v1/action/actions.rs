@@ -1,11 +1,25 @@
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::sync::LazyLock;
 use uuid::Uuid;
 
+use crate::drivers::Drivers;
+use crate::minecraft::{
+    server_version::ServerVersion, EffectiveConfig, InstType, InstanceFilter, InstanceSummary,
+};
+use crate::protocols::Protocols;
+use crate::storage::cleanup::StorageCategory;
+use crate::storage::file::{DirectoryEntry, FileStat, HashAlgo};
 use crate::storage::java::JavaInfo;
+use crate::user::userdb::{Permission, PermissionGroup, Permissions};
+use crate::user::users::{ImportMode, UserInfo, UserRecord};
 
-pub static RANGE_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(\d+)..(\d+)$").unwrap());
+use super::ErrorCode;
+
+/// `from..to`, either bound may be left out, see [`parse_range`](super::parse_range)
+pub static RANGE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\d*)\.\.(\d*)$").unwrap());
 
 #[derive(Debug, Deserialize, PartialEq, Eq)]
 #[serde(tag = "action", content = "params", rename_all = "snake_case")]
@@ -22,6 +36,8 @@ pub enum ActionRequests {
         file_id: Uuid,
         offset: u64,
         data: String,
+        /// sha1 of this chunk, rejected early on mismatch
+        sha1: Option<String>,
     },
     FileUploadCancel {
         file_id: Uuid,
@@ -33,16 +49,360 @@ pub enum ActionRequests {
         file_id: Uuid,
         range: String,
     },
+    /// only sent as a binary frame, and answered by one
+    FileDownloadRangeRaw {
+        file_id: Uuid,
+        range: String,
+    },
     FileDownloadClose {
         file_id: Uuid,
     },
+    FileListDirectory {
+        /// empty for the data root
+        #[serde(default)]
+        path: String,
+    },
+    FileDelete {
+        path: String,
+        /// needed to delete a directory along with its content
+        #[serde(default)]
+        recursive: bool,
+    },
+    GetFileHash {
+        path: String,
+        algo: HashAlgo,
+    },
+    StatFile {
+        path: String,
+    },
+    ShutdownDaemon {
+        /// stopping instances first is not supported yet, `true` is rejected
+        #[serde(default)]
+        drain: bool,
+        timeout_secs: Option<u64>,
+    },
+    RestartDaemon {},
+    CancelAll {},
+    GetSessionStats {},
+    RconCommand {
+        instance_id: Uuid,
+        command: String,
+    },
+    BroadcastMessage {
+        instance_id: Uuid,
+        message: String,
+    },
+    GetServerIcon {
+        instance_id: Uuid,
+    },
+    GetInstanceDiskUsage {
+        instance_id: Uuid,
+        #[serde(default)]
+        breakdown: bool,
+    },
+    ListInstances {
+        #[serde(flatten)]
+        filter: InstanceFilter,
+    },
+    SetServerIcon {
+        instance_id: Uuid,
+        /// base64 encoded 64x64 png
+        png: String,
+    },
+    CleanupStorage {
+        older_than_days: u64,
+        categories: Vec<StorageCategory>,
+    },
+    CreateUser {
+        name: String,
+        group: PermissionGroup,
+        #[serde(default)]
+        permissions: Permissions,
+        password: Option<String>,
+    },
+    GetDaemonInfo {},
+    GetUserInfo {
+        name: String,
+    },
+    SelfTest {},
+    SubscribeTick {
+        interval_ms: u64,
+    },
+    UnsubscribeTick {},
+    GetServerVersion {
+        instance_id: Uuid,
+    },
+    GetEffectiveConfig {
+        instance_id: Uuid,
+    },
+    DescribeActions {},
+    ExportUsers {
+        /// include password hashes and token secrets, needed to import the export again
+        #[serde(default)]
+        with_secrets: bool,
+    },
+    ImportUsers {
+        data: Vec<UserRecord>,
+        #[serde(default)]
+        mode: ImportMode,
+    },
 }
 
+impl ActionRequests {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ActionRequests::Ping {} => "ping",
+            ActionRequests::GetJavaList {} => "get_java_list",
+            ActionRequests::FileUploadRequest { .. } => "file_upload_request",
+            ActionRequests::FileUploadChunk { .. } => "file_upload_chunk",
+            ActionRequests::FileUploadCancel { .. } => "file_upload_cancel",
+            ActionRequests::FileDownloadRequest { .. } => "file_download_request",
+            ActionRequests::FileDownloadRange { .. } => "file_download_range",
+            ActionRequests::FileDownloadRangeRaw { .. } => "file_download_range_raw",
+            ActionRequests::FileDownloadClose { .. } => "file_download_close",
+            ActionRequests::FileListDirectory { .. } => "file_list_directory",
+            ActionRequests::FileDelete { .. } => "file_delete",
+            ActionRequests::GetFileHash { .. } => "get_file_hash",
+            ActionRequests::StatFile { .. } => "stat_file",
+            ActionRequests::ShutdownDaemon { .. } => "shutdown_daemon",
+            ActionRequests::RestartDaemon {} => "restart_daemon",
+            ActionRequests::CancelAll {} => "cancel_all",
+            ActionRequests::GetSessionStats {} => "get_session_stats",
+            ActionRequests::RconCommand { .. } => "rcon_command",
+            ActionRequests::BroadcastMessage { .. } => "broadcast_message",
+            ActionRequests::GetServerIcon { .. } => "get_server_icon",
+            ActionRequests::GetInstanceDiskUsage { .. } => "get_instance_disk_usage",
+            ActionRequests::ListInstances { .. } => "list_instances",
+            ActionRequests::SetServerIcon { .. } => "set_server_icon",
+            ActionRequests::CleanupStorage { .. } => "cleanup_storage",
+            ActionRequests::CreateUser { .. } => "create_user",
+            ActionRequests::GetDaemonInfo {} => "get_daemon_info",
+            ActionRequests::GetUserInfo { .. } => "get_user_info",
+            ActionRequests::SelfTest {} => "self_test",
+            ActionRequests::SubscribeTick { .. } => "subscribe_tick",
+            ActionRequests::UnsubscribeTick {} => "unsubscribe_tick",
+            ActionRequests::GetServerVersion { .. } => "get_server_version",
+            ActionRequests::GetEffectiveConfig { .. } => "get_effective_config",
+            ActionRequests::DescribeActions {} => "describe_actions",
+            ActionRequests::ExportUsers { .. } => "export_users",
+            ActionRequests::ImportUsers { .. } => "import_users",
+        }
+    }
+
+    /// permission a user needs to run the action, `None` for actions anyone may run.
+    /// actions missing from [`ACTIONS`] are for users allowed everything only
+    pub fn required_permission(&self) -> Option<Permission> {
+        match ActionInfo::find(self.name()) {
+            Some(info) => info.permission.map(Permission::from),
+            None => Some("**".into()),
+        }
+    }
+}
+
+/// what clients may learn about an action without running it
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub struct ActionInfo {
+    pub name: &'static str,
+    /// `None` if anyone may run it
+    pub permission: Option<&'static str>,
+    pub description: &'static str,
+}
+
+impl ActionInfo {
+    pub fn find(name: &str) -> Option<&'static ActionInfo> {
+        ACTIONS.iter().find(|info| info.name == name)
+    }
+}
+
+pub static ACTION_NAMES: LazyLock<Vec<&'static str>> =
+    LazyLock::new(|| ACTIONS.iter().map(|info| info.name).collect());
+
+/// all v1 actions
+pub const ACTIONS: &[ActionInfo] = &[
+    ActionInfo {
+        name: "ping",
+        permission: None,
+        description: "check the connection, returns the server time",
+    },
+    ActionInfo {
+        name: "get_java_list",
+        permission: Some("java.list"),
+        description: "list the java installations found on the host",
+    },
+    ActionInfo {
+        name: "file_upload_request",
+        permission: Some("file.write"),
+        description: "start uploading a file in chunks",
+    },
+    ActionInfo {
+        name: "file_upload_chunk",
+        permission: Some("file.write"),
+        description: "upload one chunk of a file",
+    },
+    ActionInfo {
+        name: "file_upload_cancel",
+        permission: Some("file.write"),
+        description: "cancel an upload and drop its partial file",
+    },
+    ActionInfo {
+        name: "file_download_request",
+        permission: Some("file.read"),
+        description: "start downloading a file",
+    },
+    ActionInfo {
+        name: "file_download_range",
+        permission: Some("file.read"),
+        description: "download a byte range of a file",
+    },
+    ActionInfo {
+        name: "file_download_range_raw",
+        permission: Some("file.read"),
+        description: "download a byte range of a file as a binary frame",
+    },
+    ActionInfo {
+        name: "file_download_close",
+        permission: Some("file.read"),
+        description: "end a download",
+    },
+    ActionInfo {
+        name: "file_list_directory",
+        permission: Some("file.read"),
+        description: "files and directories in a directory",
+    },
+    ActionInfo {
+        name: "file_delete",
+        permission: Some("file.write"),
+        description: "delete a file, or a directory with its content",
+    },
+    ActionInfo {
+        name: "get_file_hash",
+        permission: Some("file.read"),
+        description: "digest of a file",
+    },
+    ActionInfo {
+        name: "stat_file",
+        permission: Some("file.read"),
+        description: "size, times and cached digests of a file, without opening a session",
+    },
+    ActionInfo {
+        name: "shutdown_daemon",
+        permission: Some("daemon.shutdown"),
+        description: "stop the daemon, draining instances first is not supported yet",
+    },
+    ActionInfo {
+        name: "restart_daemon",
+        permission: Some("daemon.restart"),
+        description: "restart the daemon, if enabled",
+    },
+    ActionInfo {
+        name: "cancel_all",
+        permission: None,
+        description: "cancel the other in-flight actions of this connection",
+    },
+    ActionInfo {
+        name: "get_session_stats",
+        permission: Some("daemon.status"),
+        description: "count the open upload and download sessions",
+    },
+    ActionInfo {
+        name: "rcon_command",
+        permission: Some("instance.console.write"),
+        description: "run a command on an instance through rcon",
+    },
+    ActionInfo {
+        name: "broadcast_message",
+        permission: Some("instance.console.write"),
+        description: "say a message to the players of an instance",
+    },
+    ActionInfo {
+        name: "get_server_icon",
+        permission: Some("instance.status"),
+        description: "read the server icon of an instance",
+    },
+    ActionInfo {
+        name: "get_instance_disk_usage",
+        permission: Some("instance.status"),
+        description: "disk space taken by an instance",
+    },
+    ActionInfo {
+        name: "list_instances",
+        permission: Some("instance.status"),
+        description: "instances, optionally only those with a tag or in a group",
+    },
+    ActionInfo {
+        name: "set_server_icon",
+        permission: Some("instance.icon.write"),
+        description: "replace the server icon of an instance",
+    },
+    ActionInfo {
+        name: "cleanup_storage",
+        permission: Some("daemon.cleanup"),
+        description: "delete old logs, crash reports and backups",
+    },
+    ActionInfo {
+        name: "create_user",
+        permission: Some("user.create"),
+        description: "add a user",
+    },
+    ActionInfo {
+        name: "get_daemon_info",
+        permission: None,
+        description: "version and capabilities of the daemon",
+    },
+    ActionInfo {
+        name: "get_user_info",
+        permission: Some("user.info"),
+        description: "account details of a user",
+    },
+    ActionInfo {
+        name: "self_test",
+        permission: Some("daemon.status"),
+        description: "check the daemon can work as configured",
+    },
+    ActionInfo {
+        name: "subscribe_tick",
+        permission: None,
+        description: "receive tick events at an interval",
+    },
+    ActionInfo {
+        name: "unsubscribe_tick",
+        permission: None,
+        description: "stop receiving tick events",
+    },
+    ActionInfo {
+        name: "get_server_version",
+        permission: Some("instance.status"),
+        description: "version of the server installed in an instance",
+    },
+    ActionInfo {
+        name: "get_effective_config",
+        permission: Some("instance.status"),
+        description: "java, args and paths starting an instance would use",
+    },
+    ActionInfo {
+        name: "describe_actions",
+        permission: None,
+        description: "list the supported actions",
+    },
+    ActionInfo {
+        name: "export_users",
+        permission: Some("user.export"),
+        description: "export all users, for backups",
+    },
+    ActionInfo {
+        name: "import_users",
+        permission: Some("user.import"),
+        description: "import exported users, merging or replacing the existing ones",
+    },
+];
+
 #[derive(Debug, Serialize, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum ActionResponses {
     ActionError {
         error_message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error_code: Option<ErrorCode>,
     },
     Ping {
         time: u64,
@@ -56,6 +416,9 @@ pub enum ActionResponses {
     FileUploadChunk {
         done: bool,
         received: u64,
+        /// sha1 of all bytes received so far, if chunks were sent sequentially
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sha1: Option<String>,
     },
     FileUploadCancel {},
     FileDownloadRequest {
@@ -65,8 +428,128 @@ pub enum ActionResponses {
     },
     FileDownloadRange {
         content: String,
+        /// sha1 of all bytes served so far, if ranges were requested sequentially
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sha1: Option<String>,
+    },
+    /// header of the binary frame, the `size` bytes of content follow it
+    FileDownloadRangeRaw {
+        size: u64,
+        /// sha1 of all bytes served so far, if ranges were requested sequentially
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sha1: Option<String>,
     },
     FileDownloadClose {},
+    FileListDirectory {
+        #[serde(flatten)]
+        directory: DirectoryEntry,
+    },
+    FileDelete {},
+    GetFileHash {
+        hash: String,
+    },
+    StatFile {
+        #[serde(flatten)]
+        stat: FileStat,
+    },
+    ShutdownDaemon {},
+    RestartDaemon {},
+    CancelAll {
+        cancelled: usize,
+    },
+    GetSessionStats {
+        uploads: usize,
+        downloads: usize,
+    },
+    RconCommand {
+        response: String,
+    },
+    BroadcastMessage {},
+    GetServerIcon {
+        /// base64 encoded png
+        png: String,
+    },
+    SetServerIcon {},
+    GetInstanceDiskUsage {
+        total: u64,
+        /// size of each top level directory, e.g. world, logs
+        #[serde(skip_serializing_if = "Option::is_none")]
+        breakdown: Option<BTreeMap<String, u64>>,
+    },
+    ListInstances {
+        instances: Vec<InstanceSummary>,
+    },
+    CleanupStorage {
+        removed_files: u64,
+        reclaimed_bytes: u64,
+    },
+    CreateUser {
+        name: String,
+        /// only set if generated, it is not shown again
+        #[serde(skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+    },
+    GetDaemonInfo {
+        version: String,
+        api_version: String,
+        drivers: Vec<Drivers>,
+        protocols: Vec<Protocols>,
+        instance_types: Vec<InstType>,
+        tls: bool,
+        instance_count: usize,
+    },
+    GetUserInfo {
+        #[serde(flatten)]
+        info: UserInfo,
+    },
+    SelfTest {
+        passed: bool,
+        checks: Vec<SelfTestCheck>,
+    },
+    SubscribeTick {
+        /// the interval ticks are pushed at, after clamping
+        interval_ms: u64,
+    },
+    UnsubscribeTick {
+        subscribed: bool,
+    },
+    GetServerVersion {
+        #[serde(flatten)]
+        version: ServerVersion,
+    },
+    GetEffectiveConfig {
+        #[serde(flatten)]
+        config: EffectiveConfig,
+    },
+    DescribeActions {
+        actions: Vec<ActionInfo>,
+    },
+    ExportUsers {
+        users: Vec<UserRecord>,
+    },
+    ImportUsers {
+        imported: usize,
+        /// users kept because they already existed
+        skipped: Vec<String>,
+    },
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct SelfTestCheck {
+    pub name: String,
+    pub passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SelfTestCheck {
+    pub fn new(name: &str, result: anyhow::Result<()>) -> Self {
+        Self {
+            name: name.to_string(),
+            passed: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
@@ -337,6 +820,33 @@ mod test_request_deserialize {
         };
         assert_eq!(serde_json::from_str::<Request>(raw).unwrap(), expected);
     }
+
+    #[test]
+    fn action_table_matches_requests() {
+        let mut names = ACTION_NAMES.clone();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), ACTIONS.len());
+
+        for info in ACTIONS {
+            let raw = format!(r#"{{"action": "{}", "params": {{}}}}"#, info.name);
+            match serde_json::from_str::<Request>(&raw) {
+                Ok(request) => assert_eq!(request.request.name(), info.name),
+                // actions with required params
+                Err(err) => assert!(err.to_string().contains("missing field"), "{}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn admins_may_run_every_action() {
+        let admin = Permissions::default().with_group_defaults(&PermissionGroup::Admin);
+        for info in ACTIONS {
+            if let Some(permission) = info.permission {
+                assert!(admin.check(&permission.into()).is_ok(), "{}", permission);
+            }
+        }
+    }
 }
 
 /// test action response serialize
@@ -401,6 +911,7 @@ mod test_response_serialize {
         let expected = Response {
             data: ActionResponses::ActionError {
                 error_message: "error message".to_string(),
+                error_code: None,
             },
             status: ResponseStatus::Error,
             echo: Some("114514".to_string()),
@@ -1,5 +1,8 @@
 mod actions;
+mod validation;
 
 pub use actions::{
-    ActionRequests, ActionResponses, Request, Response, ResponseStatus, RANGE_REGEX,
+    ActionInfo, ActionRequests, ActionResponses, Request, Response, ResponseStatus, SelfTestCheck,
+    ACTION_NAMES, RANGE_REGEX,
 };
+pub use validation::{parse_range, validate_params, ErrorCode, ValidationError};
@@ -0,0 +1,245 @@
+use std::fmt::{Display, Formatter};
+
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use super::RANGE_REGEX;
+use crate::storage::{DiskError, Files};
+
+/// machine readable reason of a rejected action, sent along with the error message
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// not json, unknown action or params not matching it
+    MalformedRequest,
+    InvalidUuid,
+    /// a path outside of the data root
+    PathNotAllowed,
+    /// a malformed range, or one past the end of the file
+    RangeNotSatisfiable,
+    /// the data disk ran out of space or quota
+    DiskFull,
+    /// the data disk is mounted read-only
+    ReadOnlyFilesystem,
+}
+
+impl ErrorCode {
+    /// the code of an action error, if it has one
+    pub fn of(err: &anyhow::Error) -> Option<Self> {
+        if let Some(err) = err.downcast_ref::<ValidationError>() {
+            return Some(err.code);
+        }
+        err.downcast_ref::<DiskError>().map(|err| match err {
+            DiskError::Full => ErrorCode::DiskFull,
+            DiskError::ReadOnly => ErrorCode::ReadOnlyFilesystem,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ValidationError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+#[derive(Debug, Clone, Copy)]
+enum Check {
+    Uuid,
+    Path,
+    Range,
+}
+
+impl Check {
+    fn code(self) -> ErrorCode {
+        match self {
+            Check::Uuid => ErrorCode::InvalidUuid,
+            Check::Path => ErrorCode::PathNotAllowed,
+            Check::Range => ErrorCode::RangeNotSatisfiable,
+        }
+    }
+}
+
+/// params checked before an action is parsed, alike for every action having them
+const PARAM_CHECKS: &[(&str, Check)] = &[
+    ("file_id", Check::Uuid),
+    ("instance_id", Check::Uuid),
+    ("path", Check::Path),
+    ("range", Check::Range),
+];
+
+/// check the well-known params of a raw request, so all actions reject them with the same code
+pub fn validate_params(request: &Value) -> Result<(), ValidationError> {
+    let Some(params) = request.get("params").and_then(Value::as_object) else {
+        return Ok(());
+    };
+    for &(name, check) in PARAM_CHECKS {
+        let value = match params.get(name) {
+            // optional params are left to the action
+            None | Some(Value::Null) => continue,
+            Some(Value::String(value)) => value,
+            Some(_) => {
+                return Err(ValidationError::new(
+                    check.code(),
+                    format!("{} must be a string", name),
+                ))
+            }
+        };
+        match check {
+            Check::Uuid => {
+                if Uuid::parse_str(value).is_err() {
+                    return Err(ValidationError::new(
+                        check.code(),
+                        format!("invalid uuid in {}: {}", name, value),
+                    ));
+                }
+            }
+            // empty is the data root to the actions accepting it
+            Check::Path => {
+                if !value.is_empty() && !Files::is_in_root(value) {
+                    return Err(ValidationError::new(
+                        check.code(),
+                        format!("path outside of the data root: {}", value),
+                    ));
+                }
+            }
+            // the size is checked by the action
+            Check::Range => {
+                parse_range(value, u64::MAX)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// parse a byte range of a file of `size` bytes into `from..to`, `to` exclusive:
+/// - `from..to`
+/// - `from..` up to the end of the file
+/// - `..to` from the start of the file
+/// - `n` the single byte at `n`
+///
+/// the range must not be empty and must not pass the end of the file
+pub fn parse_range(range: &str, size: u64) -> Result<(u64, u64), ValidationError> {
+    let invalid = |reason: &str| {
+        ValidationError::new(
+            ErrorCode::RangeNotSatisfiable,
+            format!("invalid range {}: {}", range, reason),
+        )
+    };
+    let bound = |bound: &str| {
+        bound
+            .parse::<u64>()
+            .map_err(|_| invalid("bound out of bounds"))
+    };
+    let (from, to) = if let Some(captures) = RANGE_REGEX.captures(range) {
+        match (&captures[1], &captures[2]) {
+            ("", "") => return Err(invalid("expected at least one bound")),
+            (from, "") => (bound(from)?, size),
+            ("", to) => (0, bound(to)?),
+            (from, to) => (bound(from)?, bound(to)?),
+        }
+    } else if !range.is_empty() && range.bytes().all(|b| b.is_ascii_digit()) {
+        let at = bound(range)?;
+        (
+            at,
+            at.checked_add(1)
+                .ok_or_else(|| invalid("bound out of bounds"))?,
+        )
+    } else {
+        return Err(invalid("expected from..to"));
+    };
+    if from >= to {
+        return Err(invalid("from must be less than to"));
+    }
+    if to > size {
+        return Err(invalid("past the end of the file"));
+    }
+    Ok((from, to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn code(params: Value) -> Option<ErrorCode> {
+        validate_params(&json!({ "action": "test", "params": params }))
+            .err()
+            .map(|e| e.code)
+    }
+
+    #[test]
+    fn well_known_params_are_checked() {
+        assert_eq!(
+            code(json!({ "file_id": "nope" })),
+            Some(ErrorCode::InvalidUuid)
+        );
+        assert_eq!(
+            code(json!({ "instance_id": 1 })),
+            Some(ErrorCode::InvalidUuid)
+        );
+        assert_eq!(
+            code(json!({ "path": "daemon/../../etc/passwd" })),
+            Some(ErrorCode::PathNotAllowed)
+        );
+        assert_eq!(
+            code(json!({ "range": "5..2" })),
+            Some(ErrorCode::RangeNotSatisfiable)
+        );
+
+        let file_id = Uuid::new_v4();
+        assert_eq!(
+            code(json!({ "file_id": file_id, "path": "daemon/a.txt", "range": "0..4" })),
+            None
+        );
+        assert_eq!(code(json!({ "path": null })), None);
+    }
+
+    #[test]
+    fn ranges_are_checked_against_the_size() {
+        assert_eq!(parse_range("0..4", 4), Ok((0, 4)));
+        assert_eq!(
+            parse_range("0..5", 4).unwrap_err().code,
+            ErrorCode::RangeNotSatisfiable
+        );
+        assert!(parse_range("2..2", 4).is_err());
+        assert!(parse_range("a..b", 4).is_err());
+        assert!(parse_range("0..99999999999999999999", u64::MAX).is_err());
+    }
+
+    #[test]
+    fn open_and_single_byte_ranges() {
+        assert_eq!(parse_range("100..", 300), Ok((100, 300)));
+        assert_eq!(parse_range("..200", 300), Ok((0, 200)));
+        assert_eq!(parse_range("7", 300), Ok((7, 8)));
+        // nothing left to read
+        assert!(parse_range("300..", 300).is_err());
+        assert!(parse_range("300", 300).is_err());
+        assert!(parse_range("..", 300).is_err());
+        assert!(parse_range("", 300).is_err());
+        assert!(parse_range("1.2", 300).is_err());
+    }
+
+    #[test]
+    fn inverted_range_is_rejected() {
+        let err = parse_range("200..100", 300).unwrap_err();
+        assert_eq!(err.code, ErrorCode::RangeNotSatisfiable);
+    }
+}
@@ -1,9 +1,26 @@
 use serde::{Deserialize, Serialize};
 
+use crate::storage::FileModes;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ProtocolV1Config {
     pub max_parallel_requests: u16,
     pub file_download_sessions: u8,
+    /// allow admins to restart the daemon remotely (`restart_daemon` action)
+    pub allow_restart: bool,
+    /// where `.tmp` files of ongoing uploads live, should be on the same volume as the data root
+    pub upload_temp_dir: String,
+    /// largest file size an upload may declare, in bytes
+    pub max_upload_size: u64,
+    /// smallest upload chunk, unless one chunk covers the whole file
+    pub min_chunk_size: u64,
+    pub max_chunk_size: u64,
+    /// run actions on the same file session (`file_id`) in the order they arrived,
+    /// other actions still run in parallel
+    pub ordered_file_actions: bool,
+    /// modes of uploaded files and the directories created for them (unix)
+    pub file_modes: FileModes,
 }
 
 impl Default for ProtocolV1Config {
@@ -11,6 +28,13 @@ impl Default for ProtocolV1Config {
         Self {
             max_parallel_requests: 256,
             file_download_sessions: 3,
+            allow_restart: false,
+            upload_temp_dir: "daemon/tmp".to_string(),
+            max_upload_size: 64 << 30,
+            min_chunk_size: 1 << 10,
+            max_chunk_size: 8 << 20,
+            ordered_file_actions: true,
+            file_modes: FileModes::default(),
         }
     }
 }
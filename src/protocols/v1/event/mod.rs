@@ -1,3 +1,5 @@
 pub use events::Events;
+pub use tick::TickHub;
 
 mod events;
+mod tick;
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use scc::HashMap;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// shortest tick interval a client may request
+pub const MIN_TICK_INTERVAL_MS: u64 = 250;
+/// longest tick interval a client may request
+pub const MAX_TICK_INTERVAL_MS: u64 = 3_600_000;
+
+/// pushed periodically to subscribers, lets idle dashboards see the connection is live and sync clocks
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub struct Tick {
+    /// unix time in millis
+    pub time: u64,
+    /// millis since the daemon started
+    pub server_uptime: u64,
+}
+
+/// one timer per requested interval, shared by all its subscribers,
+/// a timer stops once its last subscriber is gone and [`TickHub::prune`] ran
+#[derive(Clone)]
+pub struct TickHub {
+    started: Instant,
+    // use ahash to speed up ops
+    timers: Arc<HashMap<u64, broadcast::Sender<Tick>, ahash::RandomState>>,
+}
+
+impl Default for TickHub {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            timers: Arc::default(),
+        }
+    }
+}
+
+impl TickHub {
+    pub fn clamp_interval(interval_ms: u64) -> u64 {
+        interval_ms.clamp(MIN_TICK_INTERVAL_MS, MAX_TICK_INTERVAL_MS)
+    }
+
+    /// receive ticks every `interval_ms`, clamped to
+    /// [`MIN_TICK_INTERVAL_MS`]..=[`MAX_TICK_INTERVAL_MS`]
+    pub fn subscribe(&self, interval_ms: u64) -> broadcast::Receiver<Tick> {
+        let interval_ms = Self::clamp_interval(interval_ms);
+        let entry = self.timers.entry(interval_ms).or_insert_with(|| {
+            let (tx, _) = broadcast::channel(4);
+            tokio::spawn(self.clone().run_timer(interval_ms, tx.clone()));
+            tx
+        });
+        entry.get().subscribe()
+    }
+
+    /// remove the timer of `interval_ms` if it has no subscribers left,
+    /// call it after dropping a receiver so long intervals don't linger until the next tick
+    pub fn prune(&self, interval_ms: u64) -> bool {
+        self.timers
+            .remove_if(&Self::clamp_interval(interval_ms), |tx| {
+                tx.receiver_count() == 0
+            })
+            .is_some()
+    }
+
+    async fn run_timer(self, interval_ms: u64, tx: broadcast::Sender<Tick>) {
+        let mut interval = tokio::time::interval(Duration::from_millis(interval_ms));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // the first tick completes right away
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            // pruned under the entry lock, so no one subscribes to a timer being removed.
+            // a timer already pruned can not get new subscribers either
+            if tx.receiver_count() == 0
+                && (self.prune(interval_ms)
+                    || !self
+                        .timers
+                        .read(&interval_ms, |_, current| current.same_channel(&tx))
+                        .unwrap_or(false))
+            {
+                return;
+            }
+            let _ = tx.send(self.tick());
+        }
+    }
+
+    fn tick(&self) -> Tick {
+        Tick {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            server_uptime: self.started.elapsed().as_millis() as u64,
+        }
+    }
+
+    #[cfg(test)]
+    fn timer_count(&self) -> usize {
+        self.timers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn ticks_arrive_at_the_requested_interval() {
+        let hub = TickHub::default();
+        let begin = Instant::now();
+        let mut rx = hub.subscribe(300);
+        let first = rx.recv().await.unwrap();
+        let second = rx.recv().await.unwrap();
+        let elapsed = begin.elapsed();
+        assert!(elapsed >= Duration::from_millis(600), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1200), "{:?}", elapsed);
+        assert!(second.server_uptime > first.server_uptime);
+        assert!(second.time >= first.time);
+    }
+
+    #[tokio::test]
+    async fn subscribers_share_one_timer() {
+        let hub = TickHub::default();
+        let mut a = hub.subscribe(MIN_TICK_INTERVAL_MS);
+        // clamped to the same interval
+        let mut b = hub.subscribe(1);
+        assert_eq!(hub.timer_count(), 1);
+        assert_eq!(a.recv().await.unwrap(), b.recv().await.unwrap());
+
+        drop(a);
+        drop(b);
+        tokio::time::sleep(Duration::from_millis(MIN_TICK_INTERVAL_MS * 2)).await;
+        assert_eq!(hub.timer_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn pruned_timers_stop_right_away() {
+        let hub = TickHub::default();
+        let rx = hub.subscribe(u64::MAX);
+        assert!(!hub.prune(MAX_TICK_INTERVAL_MS));
+        drop(rx);
+        assert!(hub.prune(u64::MAX));
+        assert_eq!(hub.timer_count(), 0);
+
+        // the pruned timer stops on its next tick and leaves the new one alone
+        drop(hub.subscribe(MIN_TICK_INTERVAL_MS));
+        assert!(hub.prune(MIN_TICK_INTERVAL_MS));
+        let mut rx = hub.subscribe(MIN_TICK_INTERVAL_MS);
+        rx.recv().await.unwrap();
+        rx.recv().await.unwrap();
+        assert_eq!(hub.timer_count(), 1);
+    }
+}
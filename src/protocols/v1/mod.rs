@@ -3,5 +3,8 @@ mod config;
 pub mod event;
 mod protocol;
 
+/// api version served under `/api/v1`
+pub const API_VERSION: &str = "v1";
+
 pub use config::ProtocolV1Config;
 pub use protocol::ProtocolV1;
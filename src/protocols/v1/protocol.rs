@@ -1,39 +1,112 @@
 use super::super::Protocol;
 use super::action::{
-    ActionRequests, ActionResponses, Request, Response, ResponseStatus, RANGE_REGEX,
+    parse_range, validate_params, ActionInfo, ActionRequests, ActionResponses, ErrorCode, Request,
+    Response, ResponseStatus, SelfTestCheck, ValidationError, ACTION_NAMES,
 };
-use crate::storage::{java::JavaInfo, Files};
-use crate::utils::AsyncTimedCache;
-use anyhow::{bail, Context};
+use super::event::{Events, TickHub};
+use super::{ProtocolV1Config, API_VERSION};
+use crate::drivers::{DriverStates, ShutdownRequest, ShutdownSender};
+use crate::minecraft::{
+    disk_usage::DiskUsageCache, effective_config, instance_ids_in, list_instances_in, rcon,
+    server_icon, server_version, InstType, InstanceFilter, INSTANCES_ROOT,
+};
+use crate::protocols::{ConnectionContext, Protocols};
+use crate::storage::{
+    cleanup::{self, CleanupReport, StorageCategory},
+    file::HashAlgo,
+    java::{JavaConfig, JavaInfo},
+    Files,
+};
+use crate::user::userdb::{PermissionGroup, Permissions};
+use crate::user::users::{ImportMode, User, UserRecord, Users};
+use crate::utils::{base64_decode, base64_encode, with_trace_id, AsyncTimedCache};
+use anyhow::{anyhow, bail};
+use serde_json::json;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+/// delay before a requested shutdown starts, so the response can reach the client first
+const SHUTDOWN_DELAY: Duration = Duration::from_millis(500);
+
 pub struct ProtocolV1 {
+    config: ProtocolV1Config,
+    enabled_protocols: Vec<Protocols>,
     java_scan_cache: AsyncTimedCache<Vec<JavaInfo>>,
+    /// parent of the scan tokens, cancelled on shutdown
+    java_scan_cancel_token: CancellationToken,
+    /// token of the latest scan, cancelled once a newer one starts
+    current_java_scan: std::sync::Mutex<CancellationToken>,
     files: Files,
+    disk_usage_cache: DiskUsageCache,
+    ticks: TickHub,
+    users: Arc<Users>,
+    driver_states: DriverStates,
+    shutdown_sender: ShutdownSender,
 }
 
 impl Protocol for ProtocolV1 {
-    async fn process_text(&self, raw: &str) -> Option<String> {
-        Some(serde_json::to_string_pretty(&self.process(raw).await).unwrap())
+    async fn process_text(&self, raw: &str, ctx: &Arc<ConnectionContext>) -> Option<String> {
+        let task = ctx.track();
+        // correlate logs of this action by its echo, or a generated id
+        let trace_id = Self::get_echo(raw).unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+        let response = with_trace_id(trace_id, async {
+            tokio::select! {
+                response = self.process(raw, ctx, task.id) => response,
+                _ = task.token.cancelled() => Self::err("action cancelled".to_string(), Self::get_echo(raw)),
+            }
+        })
+        .await;
+        Some(serde_json::to_string_pretty(&response).unwrap())
+    }
+
+    /// binary frames carry a json request like text frames, but are answered with
+    /// `[header length: u32 be][json response][raw content]`
+    async fn process_binary(&self, raw: &[u8], ctx: &Arc<ConnectionContext>) -> Option<Vec<u8>> {
+        let task = ctx.track();
+        let raw = String::from_utf8_lossy(raw);
+        let trace_id = Self::get_echo(&raw).unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+        let (response, content) = with_trace_id(trace_id, async {
+            tokio::select! {
+                rv = self.process_raw(&raw, ctx, task.id) => rv,
+                _ = task.token.cancelled() => (Self::err("action cancelled".to_string(), Self::get_echo(&raw)), vec![]),
+            }
+        })
+        .await;
+        Some(Self::binary_frame(&response, content))
     }
 
-    async fn process_binary(&self, _: &[u8]) -> Option<Vec<u8>> {
-        None
+    fn supported_actions(&self) -> &[&'static str] {
+        &ACTION_NAMES
     }
 }
 
 impl ProtocolV1 {
+    /// key of the actions which must not be reordered with `raw`, see [`ConnectionContext::order`]
+    pub fn ordering_key(&self, raw: &str) -> Option<Uuid> {
+        if !self.config.ordered_file_actions {
+            return None;
+        }
+        let value = serde_json::from_str::<serde_json::Value>(raw).ok()?;
+        value["params"]["file_id"].as_str()?.parse().ok()
+    }
+
     #[inline]
-    async fn process(&self, raw: &str) -> Response {
-        let parsed = match serde_json::from_str::<Request>(raw) {
+    async fn process(&self, raw: &str, ctx: &ConnectionContext, task_id: u64) -> Response {
+        let parsed = match Self::parse(raw) {
             Ok(parsed) => parsed,
             Err(err) => {
                 log::error!("action error: {}", err);
-                return Self::err(err.to_string(), Self::get_echo(raw));
+                return Self::err_with_code(err.message, err.code, Self::get_echo(raw));
             }
         };
 
+        if let Err(missing) = Self::check_permission(&parsed.request, ctx) {
+            return Self::err(missing, parsed.echo);
+        }
+
         let response = match parsed.request {
             ActionRequests::Ping {} => Self::ping_handler().await,
             ActionRequests::GetJavaList {} => self.get_java_list_handler().await,
@@ -43,42 +116,211 @@ impl ProtocolV1 {
                 chunk_size,
                 size,
             } => {
-                self.file_upload_request_handler(path, sha1, chunk_size, size)
+                self.file_upload_request_handler(ctx, path, sha1, chunk_size, size)
                     .await
             }
             ActionRequests::FileUploadChunk {
                 file_id,
                 offset,
                 data,
-            } => self.file_upload_chunk_handler(file_id, offset, data).await,
+                sha1,
+            } => {
+                self.file_upload_chunk_handler(file_id, offset, data, sha1)
+                    .await
+            }
             ActionRequests::FileUploadCancel { file_id } => {
                 self.file_upload_cancel_handler(file_id).await
             }
             ActionRequests::FileDownloadRequest { path } => {
-                self.file_download_request_handler(path).await
+                self.file_download_request_handler(ctx, path).await
             }
             ActionRequests::FileDownloadRange { file_id, range } => {
                 self.file_download_range_handler(file_id, range).await
             }
+            ActionRequests::FileDownloadRangeRaw { .. } => Err(anyhow!(
+                "file_download_range_raw must be sent as a binary frame"
+            )),
             ActionRequests::FileDownloadClose { file_id } => {
                 self.file_download_close_handler(file_id).await
             }
+            ActionRequests::FileListDirectory { path } => {
+                self.file_list_directory_handler(path).await
+            }
+            ActionRequests::FileDelete { path, recursive } => {
+                self.file_delete_handler(path, recursive).await
+            }
+            ActionRequests::GetFileHash { path, algo } => {
+                self.get_file_hash_handler(path, algo).await
+            }
+            ActionRequests::StatFile { path } => self.stat_file_handler(path).await,
+            ActionRequests::ShutdownDaemon {
+                drain,
+                timeout_secs,
+            } => self.shutdown_daemon_handler(drain, timeout_secs).await,
+            ActionRequests::RestartDaemon {} => self.restart_daemon_handler().await,
+            ActionRequests::CancelAll {} => self.cancel_all_handler(ctx, task_id).await,
+            ActionRequests::GetSessionStats {} => self.get_session_stats_handler().await,
+            ActionRequests::RconCommand {
+                instance_id,
+                command,
+            } => Self::rcon_command_handler(instance_id, command).await,
+            ActionRequests::BroadcastMessage {
+                instance_id,
+                message,
+            } => Self::broadcast_message_handler(instance_id, message).await,
+            ActionRequests::GetServerIcon { instance_id } => {
+                Self::get_server_icon_handler(instance_id).await
+            }
+            ActionRequests::ListInstances { filter } => {
+                Self::list_instances_handler(INSTANCES_ROOT, filter).await
+            }
+            ActionRequests::GetInstanceDiskUsage {
+                instance_id,
+                breakdown,
+            } => {
+                self.get_instance_disk_usage_handler(instance_id, breakdown)
+                    .await
+            }
+            ActionRequests::SetServerIcon { instance_id, png } => {
+                Self::set_server_icon_handler(instance_id, png).await
+            }
+            ActionRequests::CleanupStorage {
+                older_than_days,
+                categories,
+            } => Self::cleanup_storage_handler(older_than_days, categories).await,
+            ActionRequests::CreateUser {
+                name,
+                group,
+                permissions,
+                password,
+            } => {
+                self.create_user_handler(&ctx.user, name, group, permissions, password)
+                    .await
+            }
+            ActionRequests::GetDaemonInfo {} => self.get_daemon_info_handler().await,
+            ActionRequests::GetUserInfo { name } => self.get_user_info_handler(name).await,
+            ActionRequests::SelfTest {} => self.self_test_handler().await,
+            ActionRequests::SubscribeTick { interval_ms } => {
+                self.subscribe_tick_handler(ctx, interval_ms).await
+            }
+            ActionRequests::UnsubscribeTick {} => Self::unsubscribe_tick_handler(ctx).await,
+            ActionRequests::GetServerVersion { instance_id } => {
+                Self::get_server_version_handler(instance_id).await
+            }
+            ActionRequests::GetEffectiveConfig { instance_id } => {
+                Self::get_effective_config_handler(instance_id).await
+            }
+            ActionRequests::DescribeActions {} => self.describe_actions_handler().await,
+            ActionRequests::ExportUsers { with_secrets } => {
+                self.export_users_handler(&ctx.user, with_secrets).await
+            }
+            ActionRequests::ImportUsers { data, mode } => {
+                self.import_users_handler(&ctx.user, data, mode).await
+            }
         };
 
-        let response = match response {
-            Ok(response) => response,
+        match response {
+            Ok(response) => Self::ok(response, parsed.echo),
+            Err(err) => Self::from_error(err, Self::get_echo(raw)),
+        }
+    }
+
+    /// the binary frame counterpart of [`ProtocolV1::process`], returns the response and the content
+    async fn process_raw(
+        &self,
+        raw: &str,
+        ctx: &ConnectionContext,
+        task_id: u64,
+    ) -> (Response, Vec<u8>) {
+        let parsed = match Self::parse(raw) {
+            Ok(parsed) => parsed,
             Err(err) => {
                 log::error!("action error: {}", err);
-                return Self::err(err.to_string(), Self::get_echo(raw));
+                let response = Self::err_with_code(err.message, err.code, Self::get_echo(raw));
+                return (response, vec![]);
+            }
+        };
+
+        if let Err(missing) = Self::check_permission(&parsed.request, ctx) {
+            return (Self::err(missing, parsed.echo), vec![]);
+        }
+
+        let response = match parsed.request {
+            ActionRequests::FileDownloadRangeRaw { file_id, range } => {
+                self.file_download_range_raw_handler(file_id, range).await
             }
+            ActionRequests::CancelAll {} => self
+                .cancel_all_handler(ctx, task_id)
+                .await
+                .map(|response| (response, vec![])),
+            request => Err(anyhow!(
+                "{} can not be sent as a binary frame",
+                request.name()
+            )),
         };
-        Self::ok(response, parsed.echo)
+
+        match response {
+            Ok((response, content)) => (Self::ok(response, parsed.echo), content),
+            Err(err) => (Self::from_error(err, parsed.echo), vec![]),
+        }
+    }
+
+    /// `Err` with the missing permission if the user may not run `request`
+    fn check_permission(request: &ActionRequests, ctx: &ConnectionContext) -> Result<(), String> {
+        if let Some(required) = request.required_permission() {
+            if let Err(missing) = ctx.user.meta.permissions.check(&required) {
+                log::warn!("[audit] user '{}' denied: {}", ctx.user.usr, missing);
+                return Err(missing.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn binary_frame(response: &Response, content: Vec<u8>) -> Vec<u8> {
+        let header = serde_json::to_vec(response).unwrap();
+        let mut frame = Vec::with_capacity(4 + header.len() + content.len());
+        frame.extend_from_slice(&(header.len() as u32).to_be_bytes());
+        frame.extend(header);
+        frame.extend(content);
+        frame
+    }
+
+    /// validate the well-known params, then parse the action
+    fn parse(raw: &str) -> Result<Request, ValidationError> {
+        let invalid = |err: serde_json::Error| {
+            ValidationError::new(ErrorCode::MalformedRequest, err.to_string())
+        };
+        let value = serde_json::from_str::<serde_json::Value>(raw).map_err(invalid)?;
+        validate_params(&value)?;
+        serde_json::from_value(value).map_err(invalid)
+    }
+
+    fn from_error(err: anyhow::Error, echo: Option<String>) -> Response {
+        log::error!("action error: {}", err);
+        match ErrorCode::of(&err) {
+            Some(code) => Self::err_with_code(err.to_string(), code, echo),
+            None => Self::err(err.to_string(), echo),
+        }
     }
 
     fn err(msg: String, echo: Option<String>) -> Response {
         Response {
             status: ResponseStatus::Error,
-            data: ActionResponses::ActionError { error_message: msg },
+            data: ActionResponses::ActionError {
+                error_message: msg,
+                error_code: None,
+            },
+            echo,
+        }
+    }
+
+    fn err_with_code(msg: String, code: ErrorCode, echo: Option<String>) -> Response {
+        Response {
+            status: ResponseStatus::Error,
+            data: ActionResponses::ActionError {
+                error_message: msg,
+                error_code: Some(code),
+            },
             echo,
         }
     }
@@ -110,13 +352,14 @@ impl ProtocolV1 {
     #[inline]
     async fn get_java_list_handler(&self) -> anyhow::Result<ActionResponses> {
         Ok(ActionResponses::GetJavaList {
-            java_list: self.java_scan_cache.get().await,
+            java_list: self.java_list().await?,
         })
     }
 
     #[inline]
     async fn file_upload_request_handler(
         &self,
+        ctx: &ConnectionContext,
         path: Option<String>,
         sha1: Option<String>,
         chunk_size: u64,
@@ -126,6 +369,7 @@ impl ProtocolV1 {
             .files
             .upload_request(path.as_deref(), size, chunk_size, sha1.as_deref())
             .await?;
+        ctx.add_session(file_id);
         Ok(ActionResponses::FileUploadRequest { file_id })
     }
 
@@ -135,9 +379,17 @@ impl ProtocolV1 {
         file_id: Uuid,
         offset: u64,
         data: String,
+        sha1: Option<String>,
     ) -> anyhow::Result<ActionResponses> {
-        let (done, received) = self.files.upload_chunk(file_id, offset, data).await?;
-        Ok(ActionResponses::FileUploadChunk { done, received })
+        let (done, received, sha1) = self
+            .files
+            .upload_chunk(file_id, offset, data, sha1.as_deref())
+            .await?;
+        Ok(ActionResponses::FileUploadChunk {
+            done,
+            received,
+            sha1,
+        })
     }
 
     #[inline]
@@ -150,8 +402,13 @@ impl ProtocolV1 {
     }
 
     #[inline]
-    async fn file_download_request_handler(&self, path: String) -> anyhow::Result<ActionResponses> {
+    async fn file_download_request_handler(
+        &self,
+        ctx: &ConnectionContext,
+        path: String,
+    ) -> anyhow::Result<ActionResponses> {
         let (file_id, size, sha1) = self.files.download_request(&path).await?;
+        ctx.add_session(file_id);
         Ok(ActionResponses::FileDownloadRequest {
             file_id,
             size,
@@ -165,26 +422,36 @@ impl ProtocolV1 {
         file_id: Uuid,
         range: String,
     ) -> anyhow::Result<ActionResponses> {
-        let range_match = RANGE_REGEX.captures(&range);
-        if range_match.is_none() {
-            bail!("invalid range");
-        }
-        let range_match = range_match.unwrap();
-        let from: u64 = range_match
-            .get(1)
-            .unwrap()
-            .as_str()
-            .parse()
-            .context("invalid range")?;
-        let to: u64 = range_match
-            .get(2)
-            .unwrap()
-            .as_str()
-            .parse()
-            .context("invalid range")?;
+        let size = self
+            .files
+            .download_size(file_id)
+            .await
+            .ok_or(anyhow!("download id not found"))?;
+        let (from, to) = parse_range(&range, size)?;
+
+        let (content, sha1) = self.files.download_range(file_id, from, to).await?;
+        Ok(ActionResponses::FileDownloadRange { content, sha1 })
+    }
 
-        let content = self.files.download_range(file_id, from, to).await?;
-        Ok(ActionResponses::FileDownloadRange { content })
+    #[inline]
+    async fn file_download_range_raw_handler(
+        &self,
+        file_id: Uuid,
+        range: String,
+    ) -> anyhow::Result<(ActionResponses, Vec<u8>)> {
+        let size = self
+            .files
+            .download_size(file_id)
+            .await
+            .ok_or(anyhow!("download id not found"))?;
+        let (from, to) = parse_range(&range, size)?;
+
+        let (content, sha1) = self.files.download_range_raw(file_id, from, to).await?;
+        let size = content.len() as u64;
+        Ok((
+            ActionResponses::FileDownloadRangeRaw { size, sha1 },
+            content,
+        ))
     }
 
     #[inline]
@@ -192,13 +459,443 @@ impl ProtocolV1 {
         self.files.download_close(file_id).await?;
         Ok(ActionResponses::FileDownloadClose {})
     }
+
+    #[inline]
+    async fn file_list_directory_handler(&self, path: String) -> anyhow::Result<ActionResponses> {
+        let directory = self.files.list_directory(&path).await?;
+        Ok(ActionResponses::FileListDirectory { directory })
+    }
+
+    #[inline]
+    async fn file_delete_handler(
+        &self,
+        path: String,
+        recursive: bool,
+    ) -> anyhow::Result<ActionResponses> {
+        self.files.delete(&path, recursive).await?;
+        Ok(ActionResponses::FileDelete {})
+    }
+
+    /// cancel every other in-flight action of the connection and close its file sessions
+    #[inline]
+    async fn cancel_all_handler(
+        &self,
+        ctx: &ConnectionContext,
+        task_id: u64,
+    ) -> anyhow::Result<ActionResponses> {
+        let cancelled = ctx.cancel_all(Some(task_id));
+        for file_id in ctx.sessions() {
+            if self.files.upload_cancel(file_id).await {
+                log::debug!(
+                    "upload {} cancelled with the actions of its connection",
+                    file_id
+                );
+            }
+        }
+        self.close_downloads(ctx).await;
+        Ok(ActionResponses::CancelAll { cancelled })
+    }
+
+    #[inline]
+    async fn get_session_stats_handler(&self) -> anyhow::Result<ActionResponses> {
+        let (uploads, downloads) = self.files.session_stats();
+        Ok(ActionResponses::GetSessionStats { uploads, downloads })
+    }
+
+    #[inline]
+    async fn rcon_command_handler(
+        instance_id: Uuid,
+        command: String,
+    ) -> anyhow::Result<ActionResponses> {
+        let response = rcon::instance_command(instance_id, &command).await?;
+        Ok(ActionResponses::RconCommand { response })
+    }
+
+    #[inline]
+    async fn broadcast_message_handler(
+        instance_id: Uuid,
+        message: String,
+    ) -> anyhow::Result<ActionResponses> {
+        rcon::instance_command(instance_id, &rcon::say_command(&message)).await?;
+        Ok(ActionResponses::BroadcastMessage {})
+    }
+
+    #[inline]
+    async fn get_server_icon_handler(instance_id: Uuid) -> anyhow::Result<ActionResponses> {
+        let png = server_icon::load_for_instance(instance_id).await?;
+        Ok(ActionResponses::GetServerIcon {
+            png: base64_encode(&png),
+        })
+    }
+
+    #[inline]
+    async fn set_server_icon_handler(
+        instance_id: Uuid,
+        png: String,
+    ) -> anyhow::Result<ActionResponses> {
+        let png =
+            base64_decode(&png).map_err(|e| anyhow!("server icon is not valid base64: {}", e))?;
+        server_icon::save_for_instance(instance_id, &png).await?;
+        Ok(ActionResponses::SetServerIcon {})
+    }
+
+    #[inline]
+    async fn get_instance_disk_usage_handler(
+        &self,
+        instance_id: Uuid,
+        breakdown: bool,
+    ) -> anyhow::Result<ActionResponses> {
+        let usage = self.disk_usage_cache.get(instance_id).await?;
+        Ok(ActionResponses::GetInstanceDiskUsage {
+            total: usage.total,
+            breakdown: breakdown.then_some(usage.breakdown),
+        })
+    }
+
+    #[inline]
+    async fn list_instances_handler<P: AsRef<std::path::Path>>(
+        instances_root: P,
+        filter: InstanceFilter,
+    ) -> anyhow::Result<ActionResponses> {
+        Ok(ActionResponses::ListInstances {
+            instances: list_instances_in(instances_root, &filter).await,
+        })
+    }
+
+    #[inline]
+    async fn cleanup_storage_handler(
+        older_than_days: u64,
+        categories: Vec<StorageCategory>,
+    ) -> anyhow::Result<ActionResponses> {
+        let older_than = Duration::from_secs(older_than_days.saturating_mul(24 * 3600));
+        let CleanupReport {
+            removed_files,
+            reclaimed_bytes,
+        } = cleanup::cleanup(&categories, older_than).await?;
+        log::info!(
+            "storage cleanup removed {} files, {} bytes",
+            removed_files,
+            reclaimed_bytes
+        );
+        Ok(ActionResponses::CleanupStorage {
+            removed_files,
+            reclaimed_bytes,
+        })
+    }
+
+    #[inline]
+    async fn create_user_handler(
+        &self,
+        user: &User,
+        name: String,
+        group: PermissionGroup,
+        permissions: Permissions,
+        password: Option<String>,
+    ) -> anyhow::Result<ActionResponses> {
+        let password = self
+            .users
+            .create_user(&name, group, permissions, password.as_deref())
+            .await?;
+        log::info!("user '{}' created by '{}'", name, user.usr);
+        Ok(ActionResponses::CreateUser { name, password })
+    }
+
+    #[inline]
+    async fn export_users_handler(
+        &self,
+        user: &User,
+        with_secrets: bool,
+    ) -> anyhow::Result<ActionResponses> {
+        let users = self.users.export(with_secrets).await?;
+        log::info!(
+            "{} users exported by '{}', with secrets: {}",
+            users.len(),
+            user.usr,
+            with_secrets
+        );
+        Ok(ActionResponses::ExportUsers { users })
+    }
+
+    #[inline]
+    async fn import_users_handler(
+        &self,
+        user: &User,
+        data: Vec<UserRecord>,
+        mode: ImportMode,
+    ) -> anyhow::Result<ActionResponses> {
+        let total = data.len();
+        let skipped = self.users.import(data, mode, &user.usr).await?;
+        log::info!(
+            "{} users imported by '{}' ({:?}), {} skipped",
+            total - skipped.len(),
+            user.usr,
+            mode,
+            skipped.len()
+        );
+        Ok(ActionResponses::ImportUsers {
+            imported: total - skipped.len(),
+            skipped,
+        })
+    }
+
+    #[inline]
+    async fn subscribe_tick_handler(
+        &self,
+        ctx: &ConnectionContext,
+        interval_ms: u64,
+    ) -> anyhow::Result<ActionResponses> {
+        let Some(events) = ctx.events() else {
+            bail!("events are not supported on this connection");
+        };
+        let events = events.downgrade();
+        let interval_ms = TickHub::clamp_interval(interval_ms);
+        let hub = self.ticks.clone();
+        let mut ticks = hub.subscribe(interval_ms);
+        let token = ctx.subscribe(Events::Tick);
+
+        tokio::spawn(async move {
+            loop {
+                let tick = tokio::select! {
+                    tick = ticks.recv() => match tick {
+                        Ok(tick) => tick,
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    _ = token.cancelled() => break,
+                };
+                let Some(events) = events.upgrade() else {
+                    break;
+                };
+                if events.send((Events::Tick, json!(tick))).is_err() {
+                    break;
+                }
+            }
+            drop(ticks);
+            hub.prune(interval_ms);
+        });
+        Ok(ActionResponses::SubscribeTick { interval_ms })
+    }
+
+    #[inline]
+    async fn unsubscribe_tick_handler(ctx: &ConnectionContext) -> anyhow::Result<ActionResponses> {
+        Ok(ActionResponses::UnsubscribeTick {
+            subscribed: ctx.unsubscribe(Events::Tick),
+        })
+    }
+
+    #[inline]
+    async fn describe_actions_handler(&self) -> anyhow::Result<ActionResponses> {
+        let actions = self
+            .supported_actions()
+            .iter()
+            .filter_map(|name| ActionInfo::find(name))
+            .copied()
+            .collect();
+        Ok(ActionResponses::DescribeActions { actions })
+    }
+
+    #[inline]
+    async fn get_server_version_handler(instance_id: Uuid) -> anyhow::Result<ActionResponses> {
+        let version = server_version::read_for_instance(instance_id).await?;
+        Ok(ActionResponses::GetServerVersion { version })
+    }
+
+    #[inline]
+    async fn get_effective_config_handler(instance_id: Uuid) -> anyhow::Result<ActionResponses> {
+        let config = effective_config(instance_id).await?;
+        Ok(ActionResponses::GetEffectiveConfig { config })
+    }
+
+    #[inline]
+    async fn get_daemon_info_handler(&self) -> anyhow::Result<ActionResponses> {
+        Ok(self.daemon_info_in(INSTANCES_ROOT).await)
+    }
+
+    /// the daemon info, counting the instances in `instances_root`
+    async fn daemon_info_in<P: AsRef<std::path::Path>>(
+        &self,
+        instances_root: P,
+    ) -> ActionResponses {
+        ActionResponses::GetDaemonInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            api_version: API_VERSION.to_string(),
+            drivers: self.driver_states.enabled(),
+            protocols: self.enabled_protocols.clone(),
+            instance_types: InstType::ALL.to_vec(),
+            // no driver terminates tls yet, it is left to a reverse proxy
+            tls: false,
+            instance_count: instance_ids_in(instances_root).await.len(),
+        }
+    }
+
+    #[inline]
+    async fn get_user_info_handler(&self, name: String) -> anyhow::Result<ActionResponses> {
+        match self.users.user_info(&name).await {
+            Some(info) => Ok(ActionResponses::GetUserInfo { info }),
+            None => bail!("user not found"),
+        }
+    }
+
+    #[inline]
+    async fn self_test_handler(&self) -> anyhow::Result<ActionResponses> {
+        let java = async {
+            if self.java_list().await?.is_empty() {
+                bail!("no java found");
+            }
+            Ok(())
+        };
+        let drivers = async {
+            let unbound = self.driver_states.unbound();
+            if !unbound.is_empty() {
+                bail!("drivers not listening: {:?}", unbound);
+            }
+            Ok(())
+        };
+        let (db, data_dir, java, drivers) =
+            tokio::join!(self.users.ping(), Files::check_writable(), java, drivers);
+
+        let checks = vec![
+            SelfTestCheck::new("db", db),
+            SelfTestCheck::new("data_dir", data_dir),
+            SelfTestCheck::new("java", java),
+            SelfTestCheck::new("drivers", drivers),
+        ];
+        Ok(ActionResponses::SelfTest {
+            passed: checks.iter().all(|c| c.passed),
+            checks,
+        })
+    }
+
+    #[inline]
+    async fn get_file_hash_handler(
+        &self,
+        path: String,
+        algo: HashAlgo,
+    ) -> anyhow::Result<ActionResponses> {
+        let hash = self.files.get_digest(&path, algo).await?;
+        Ok(ActionResponses::GetFileHash { hash })
+    }
+
+    #[inline]
+    async fn stat_file_handler(&self, path: String) -> anyhow::Result<ActionResponses> {
+        let stat = self.files.stat(&path).await?;
+        Ok(ActionResponses::StatFile { stat })
+    }
+
+    #[inline]
+    async fn shutdown_daemon_handler(
+        &self,
+        drain: bool,
+        timeout_secs: Option<u64>,
+    ) -> anyhow::Result<ActionResponses> {
+        if drain {
+            bail!("draining instances is not supported, the daemon does not run instances yet");
+        }
+        self.request_shutdown(ShutdownRequest {
+            timeout: timeout_secs.map(Duration::from_secs),
+            restart: false,
+        });
+        Ok(ActionResponses::ShutdownDaemon {})
+    }
+
+    #[inline]
+    async fn restart_daemon_handler(&self) -> anyhow::Result<ActionResponses> {
+        if !self.config.allow_restart {
+            bail!("daemon restart is disabled");
+        }
+
+        self.request_shutdown(ShutdownRequest {
+            timeout: None,
+            restart: true,
+        });
+        Ok(ActionResponses::RestartDaemon {})
+    }
+
+    /// close the downloads a connection left open, its uploads are left alone
+    pub async fn close_downloads(&self, ctx: &ConnectionContext) {
+        for file_id in ctx.sessions() {
+            if self.files.download_close(file_id).await.is_ok() {
+                log::debug!("download {} closed with its connection", file_id);
+            }
+        }
+    }
+
+    /// warn about file sessions a closing connection left open
+    #[cfg(feature = "session_leak_check")]
+    pub fn check_session_leaks(&self, ctx: &ConnectionContext) {
+        let leaked = ctx
+            .sessions()
+            .into_iter()
+            .filter(|id| self.files.has_session(id))
+            .collect::<Vec<_>>();
+        if !leaked.is_empty() {
+            log::warn!(
+                "connection of user '{}' closed with {} file session(s) left open: {:?}",
+                ctx.user.usr,
+                leaked.len(),
+                leaked
+            );
+        }
+    }
+
+    /// the cached java list, or a fresh scan superseding the one in flight.
+    /// a superseded caller gets the result of the newer scan.
+    ///
+    /// the scan stops once the calling action is dropped, e.g. when its connection closes
+    async fn java_list(&self) -> anyhow::Result<Vec<JavaInfo>> {
+        // cancelled only when the calling action is dropped or the daemon shuts down
+        let caller = self.java_scan_cancel_token.child_token();
+        let _guard = caller.clone().drop_guard();
+        let scan = caller.child_token();
+        std::mem::replace(&mut *self.current_java_scan.lock().unwrap(), scan.clone()).cancel();
+        if let Some(list) = self.java_scan_cache.get(&scan).await {
+            return Ok(list);
+        }
+        // superseded: the cache is locked until the newer scan is done, so this waits for
+        // its result. the retry is not registered as current, no later call can cancel it
+        self.java_scan_cache
+            .get(&caller)
+            .await
+            .ok_or(anyhow!("java scan cancelled"))
+    }
+
+    fn request_shutdown(&self, request: ShutdownRequest) {
+        // don't let an in-flight java scan hold up the shutdown
+        self.java_scan_cancel_token.cancel();
+
+        let sender = self.shutdown_sender.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(SHUTDOWN_DELAY).await;
+            if sender.send(request).is_err() {
+                log::warn!("could not request shutdown: graceful shutdown is not watching");
+            }
+        });
+    }
 }
 
 impl ProtocolV1 {
-    pub fn new(files: Files) -> Self {
+    pub fn new(
+        config: ProtocolV1Config,
+        enabled_protocols: Vec<Protocols>,
+        java_config: JavaConfig,
+        files: Files,
+        users: Arc<Users>,
+        driver_states: DriverStates,
+        shutdown_sender: ShutdownSender,
+    ) -> Self {
+        let java_scan_cancel_token = CancellationToken::new();
         Self {
-            java_scan_cache: AsyncTimedCache::new(Duration::from_secs(60)),
+            config,
+            enabled_protocols,
+            java_scan_cache: AsyncTimedCache::new(Duration::from_secs(60), java_config),
+            current_java_scan: std::sync::Mutex::new(java_scan_cancel_token.child_token()),
+            java_scan_cancel_token,
             files,
+            disk_usage_cache: DiskUsageCache::default(),
+            ticks: TickHub::default(),
+            users,
+            driver_states,
+            shutdown_sender,
         }
     }
 }
@@ -320,6 +1017,7 @@ mod test_response_serialize {
         let expected = Response {
             data: ActionResponses::ActionError {
                 error_message: "error message".to_string(),
+                error_code: None,
             },
             status: ResponseStatus::Error,
             echo: Some("114514".to_string()),
@@ -327,3 +1025,672 @@ mod test_response_serialize {
         assert_eq!(serde_json::to_string_pretty(&expected).unwrap(), raw);
     }
 }
+
+/// test admin actions
+#[cfg(test)]
+mod test_admin_actions {
+    use super::*;
+    use crate::drivers::Drivers;
+    use crate::protocols::ProtocolConfig;
+    use crate::user::userdb::PermissionGroup;
+    use crate::user::users::UsersManager;
+    use crate::user::AuthConfig;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    async fn protocol(config: ProtocolV1Config, tx: ShutdownSender) -> ProtocolV1 {
+        ProtocolV1::new(
+            config,
+            vec![Protocols::V1],
+            JavaConfig::default(),
+            Files::new(ProtocolConfig::default()),
+            Arc::new(
+                Users::build(":memory:", AuthConfig::default())
+                    .await
+                    .unwrap(),
+            ),
+            DriverStates::default(),
+            tx,
+        )
+    }
+
+    fn context(group: PermissionGroup) -> Arc<ConnectionContext> {
+        Arc::new(ConnectionContext::new(User::test(group)))
+    }
+
+    async fn process(v1: &ProtocolV1, raw: &str, ctx: &Arc<ConnectionContext>) -> Response {
+        let task = ctx.track();
+        v1.process(raw, ctx, task.id).await
+    }
+
+    #[tokio::test]
+    async fn shutdown_daemon_requests_shutdown() {
+        let (tx, mut rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        let raw = r#"{"action": "shutdown_daemon", "params": {"timeout_secs": 5}}"#;
+
+        let response = process(&v1, raw, &context(PermissionGroup::Admin)).await;
+        assert_eq!(response.status, ResponseStatus::Ok);
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            ShutdownRequest {
+                timeout: Some(Duration::from_secs(5)),
+                restart: false,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn shutdown_daemon_rejects_drain() {
+        let (tx, mut rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        let raw = r#"{"action": "shutdown_daemon", "params": {"drain": true}}"#;
+
+        let response = process(&v1, raw, &context(PermissionGroup::Admin)).await;
+        assert_eq!(response.status, ResponseStatus::Error);
+        tokio::time::sleep(SHUTDOWN_DELAY * 2).await;
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn restart_daemon_requires_config_flag() {
+        let (tx, mut rx) = unbounded_channel();
+        let raw = r#"{"action": "restart_daemon", "params": {}}"#;
+
+        let v1 = protocol(ProtocolV1Config::default(), tx.clone()).await;
+        let response = process(&v1, raw, &context(PermissionGroup::Admin)).await;
+        assert_eq!(response.status, ResponseStatus::Error);
+
+        let v1 = protocol(
+            ProtocolV1Config {
+                allow_restart: true,
+                ..Default::default()
+            },
+            tx,
+        )
+        .await;
+        let response = process(&v1, raw, &context(PermissionGroup::Admin)).await;
+        assert_eq!(response.status, ResponseStatus::Ok);
+        assert!(rx.recv().await.unwrap().restart);
+    }
+
+    #[tokio::test]
+    async fn shutdown_daemon_requires_permission() {
+        let (tx, mut rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        let raw = r#"{"action": "shutdown_daemon", "params": {"drain": false}}"#;
+
+        let response = process(&v1, raw, &context(PermissionGroup::User)).await;
+        assert_eq!(response.status, ResponseStatus::Error);
+        let ActionResponses::ActionError { error_message, .. } = response.data else {
+            panic!("unexpected response {:?}", response.data);
+        };
+        assert_eq!(error_message, "missing permission daemon.shutdown");
+        tokio::time::sleep(SHUTDOWN_DELAY * 2).await;
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn tick_events_are_pushed_until_unsubscribed() {
+        let (tx, _rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        let (events_tx, mut events_rx) = unbounded_channel();
+        let ctx = Arc::new(
+            ConnectionContext::new(User::test(PermissionGroup::User)).with_events(&events_tx),
+        );
+
+        let raw = r#"{"action": "subscribe_tick", "params": {"interval_ms": 300}}"#;
+        let response = process(&v1, raw, &ctx).await;
+        assert_eq!(
+            response.data,
+            ActionResponses::SubscribeTick { interval_ms: 300 }
+        );
+
+        let begin = std::time::Instant::now();
+        for _ in 0..2 {
+            let (event, data) = events_rx.recv().await.unwrap();
+            assert_eq!(event, Events::Tick);
+            assert!(data["server_uptime"].is_u64());
+        }
+        let elapsed = begin.elapsed();
+        assert!(elapsed >= Duration::from_millis(500), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1200), "{:?}", elapsed);
+
+        let raw = r#"{"action": "unsubscribe_tick", "params": {}}"#;
+        let response = process(&v1, raw, &ctx).await;
+        assert_eq!(
+            response.data,
+            ActionResponses::UnsubscribeTick { subscribed: true }
+        );
+        while events_rx.try_recv().is_ok() {}
+        tokio::time::sleep(Duration::from_millis(700)).await;
+        assert!(events_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn tick_interval_is_clamped() {
+        let (tx, _rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        let (events_tx, _events_rx) = unbounded_channel();
+        let ctx = Arc::new(
+            ConnectionContext::new(User::test(PermissionGroup::User)).with_events(&events_tx),
+        );
+
+        let raw = r#"{"action": "subscribe_tick", "params": {"interval_ms": 1}}"#;
+        let response = process(&v1, raw, &ctx).await;
+        assert_eq!(
+            response.data,
+            ActionResponses::SubscribeTick {
+                interval_ms: TickHub::clamp_interval(0)
+            }
+        );
+
+        // connections without an event channel can not subscribe
+        let response = process(&v1, raw, &context(PermissionGroup::User)).await;
+        assert_eq!(response.status, ResponseStatus::Error);
+    }
+
+    #[tokio::test]
+    async fn cancel_all_cancels_other_actions() {
+        let (tx, _rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        let ctx = context(PermissionGroup::User);
+        let running = (0..3).map(|_| ctx.track()).collect::<Vec<_>>();
+
+        let response = process(&v1, r#"{"action": "cancel_all", "params": {}}"#, &ctx).await;
+        assert_eq!(response.data, ActionResponses::CancelAll { cancelled: 3 });
+        assert!(running.iter().all(|task| task.token.is_cancelled()));
+
+        // sent as a binary frame, it does not cancel itself either
+        let running = ctx.track();
+        let frame = v1
+            .process_binary(br#"{"action": "cancel_all", "params": {}}"#, &ctx)
+            .await
+            .unwrap();
+        let (header, bytes) = split_frame(&frame);
+        assert_eq!(header["status"], "ok");
+        assert_eq!(header["data"]["cancelled"], 1);
+        assert!(bytes.is_empty());
+        assert!(running.token.is_cancelled());
+    }
+
+    /// a fake `java` printing a version banner
+    #[cfg(unix)]
+    fn fake_java(dir: &std::path::Path) -> std::path::PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let path = dir.join("java");
+        std::fs::write(
+            &path,
+            "#!/bin/sh\necho 'openjdk version \"17.0.2\" 2022-01-18' >&2\necho 'OpenJDK 64-Bit Server VM' >&2\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    /// a protocol scanning `dir`, holding a `java` taking a second to answer
+    #[cfg(unix)]
+    async fn slow_java_protocol(dir: &std::path::Path, tx: ShutdownSender) -> Arc<ProtocolV1> {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::create_dir_all(dir).unwrap();
+        let java = dir.join("java");
+        std::fs::write(
+            &java,
+            "#!/bin/sh\nsleep 1\necho 'openjdk version \"17.0.2\" 2022-01-18' >&2\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&java, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        Arc::new(ProtocolV1::new(
+            ProtocolV1Config::default(),
+            vec![Protocols::V1],
+            JavaConfig {
+                java_scan_roots: vec![dir.to_path_buf()],
+                ..Default::default()
+            },
+            Files::new(ProtocolConfig::default()),
+            Arc::new(
+                Users::build(":memory:", AuthConfig::default())
+                    .await
+                    .unwrap(),
+            ),
+            DriverStates::default(),
+            tx,
+        ))
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cancel_all_stops_actions_and_transfers() {
+        let (tx, _rx) = unbounded_channel();
+        let dir = crate::utils::temp_path("mcsl-cancel-all");
+        let v1 = slow_java_protocol(&dir, tx).await;
+        let ctx = context(PermissionGroup::Admin);
+        let path = format!("daemon/test-{}.bin", Uuid::new_v4());
+        std::fs::create_dir_all("daemon").unwrap();
+        std::fs::write(&path, b"abcd").unwrap();
+
+        let upload = json!({
+            "action": "file_upload_request",
+            "params": {"path": format!("{}.upload", path), "chunk_size": 4, "size": 4},
+        });
+        let response = process(&v1, &upload.to_string(), &ctx).await;
+        assert_eq!(response.status, ResponseStatus::Ok);
+        let download = json!({"action": "file_download_request", "params": {"path": path}});
+        let response = process(&v1, &download.to_string(), &ctx).await;
+        assert_eq!(response.status, ResponseStatus::Ok);
+        assert_eq!(v1.files.session_stats(), (1, 1));
+
+        let scan = tokio::spawn({
+            let (v1, ctx) = (v1.clone(), ctx.clone());
+            async move {
+                let raw = r#"{"action": "get_java_list", "params": {}}"#;
+                v1.process_text(raw, &ctx).await.unwrap()
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let response = process(&v1, r#"{"action": "cancel_all", "params": {}}"#, &ctx).await;
+        assert_eq!(response.data, ActionResponses::CancelAll { cancelled: 1 });
+        assert!(scan.await.unwrap().contains("action cancelled"));
+        assert_eq!(v1.files.session_stats(), (0, 0));
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn superseded_java_scan_gets_the_newer_result() {
+        let (tx, _rx) = unbounded_channel();
+        let dir = crate::utils::temp_path("mcsl-java-scan");
+        let v1 = slow_java_protocol(&dir, tx).await;
+        let first = tokio::spawn({
+            let v1 = v1.clone();
+            async move { v1.java_list().await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let second = v1.java_list().await.unwrap();
+        assert_eq!(second.len(), 1);
+        assert_eq!(first.await.unwrap().unwrap(), second);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn self_test_reports_each_check() {
+        let (tx, _rx) = unbounded_channel();
+        let dir = crate::utils::temp_path("mcsl-self-test");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let users = Arc::new(
+            Users::build(":memory:", AuthConfig::default())
+                .await
+                .unwrap(),
+        );
+        let driver_states = DriverStates::new(&[Drivers::Websocket]);
+        driver_states.set_bound(Drivers::Websocket, true);
+        let v1 = ProtocolV1::new(
+            ProtocolV1Config::default(),
+            vec![Protocols::V1],
+            JavaConfig {
+                java_paths: vec![fake_java(&dir)],
+                ..Default::default()
+            },
+            Files::new(ProtocolConfig::default()),
+            users.clone(),
+            driver_states,
+            tx,
+        );
+        let raw = r#"{"action": "self_test", "params": {}}"#;
+        let admin = context(PermissionGroup::Admin);
+
+        let response = process(&v1, raw, &admin).await;
+        let ActionResponses::SelfTest { passed, checks } = response.data else {
+            panic!("unexpected response: {:?}", response.data);
+        };
+        assert!(passed, "{:?}", checks);
+        assert_eq!(checks.len(), 4);
+
+        users.shutdown().await.unwrap();
+        let response = process(&v1, raw, &admin).await;
+        let ActionResponses::SelfTest { passed, checks } = response.data else {
+            panic!("unexpected response: {:?}", response.data);
+        };
+        assert!(!passed);
+        let failed = checks.iter().filter(|c| !c.passed).collect::<Vec<_>>();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].name, "db");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn create_user_generates_password() {
+        let (tx, _rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        let ctx = context(PermissionGroup::Admin);
+        let raw = r#"{"action": "create_user", "params": {"name": "alice", "group": "User"}}"#;
+
+        let response = process(&v1, raw, &ctx).await;
+        let ActionResponses::CreateUser { name, password } = response.data else {
+            panic!("unexpected response: {:?}", response);
+        };
+        assert_eq!(name, "alice");
+        assert!(v1.users.auth("alice", &password.unwrap()).await.is_some());
+
+        let duplicate = process(&v1, raw, &ctx).await;
+        assert_eq!(duplicate.status, ResponseStatus::Error);
+
+        let denied = process(&v1, raw, &context(PermissionGroup::User)).await;
+        assert_eq!(denied.status, ResponseStatus::Error);
+    }
+
+    #[tokio::test]
+    async fn invalid_params_are_reported_with_a_code() {
+        let (tx, _rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        let ctx = context(PermissionGroup::Admin);
+        let error_code = |response: Response| match response.data {
+            ActionResponses::ActionError { error_code, .. } => error_code,
+            _ => None,
+        };
+
+        let raw = r#"{"action": "file_download_close", "params": {"file_id": "nope"}}"#;
+        let response = process(&v1, raw, &ctx).await;
+        assert_eq!(error_code(response), Some(ErrorCode::InvalidUuid));
+
+        let raw =
+            r#"{"action": "file_download_request", "params": {"path": "daemon/../../etc/passwd"}}"#;
+        let response = process(&v1, raw, &ctx).await;
+        assert_eq!(error_code(response), Some(ErrorCode::PathNotAllowed));
+
+        let raw = format!(
+            r#"{{"action": "file_download_range", "params": {{"file_id": "{}", "range": "5..2"}}}}"#,
+            Uuid::new_v4()
+        );
+        let response = process(&v1, &raw, &ctx).await;
+        assert_eq!(error_code(response), Some(ErrorCode::RangeNotSatisfiable));
+
+        let raw = r#"{"action": "no_such_action", "params": {}}"#;
+        let response = process(&v1, raw, &ctx).await;
+        assert_eq!(error_code(response), Some(ErrorCode::MalformedRequest));
+    }
+
+    #[tokio::test]
+    async fn actions_are_described() {
+        let (tx, _rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        for name in ["ping", "file_upload_request", "file_download_range"] {
+            assert!(v1.supported_actions().contains(&name));
+        }
+
+        let raw = r#"{"action": "describe_actions", "params": {}}"#;
+        let response = process(&v1, raw, &context(PermissionGroup::User)).await;
+        let ActionResponses::DescribeActions { actions } = response.data else {
+            panic!("unexpected response {:?}", response.data);
+        };
+        assert_eq!(actions.len(), v1.supported_actions().len());
+        let upload = actions
+            .iter()
+            .find(|info| info.name == "file_upload_chunk")
+            .unwrap();
+        assert_eq!(upload.permission, Some("file.write"));
+        assert!(!upload.description.is_empty());
+    }
+
+    #[tokio::test]
+    async fn missing_permission_is_reported() {
+        let (tx, _rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        let raw = r#"{"action": "file_upload_request", "params": {"path": "daemon/a.txt", "chunk_size": 4, "size": 4}, "echo": "1"}"#;
+
+        let response = process(&v1, raw, &context(PermissionGroup::User)).await;
+        assert_eq!(response.status, ResponseStatus::Error);
+        assert_eq!(
+            response.data,
+            ActionResponses::ActionError {
+                error_message: "missing permission file.write".to_string(),
+                error_code: None,
+            }
+        );
+        assert_eq!(response.echo.as_deref(), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn user_group_can_list_instances() {
+        let (tx, _rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        let raw = r#"{"action": "list_instances", "params": {}}"#;
+
+        let response = process(&v1, raw, &context(PermissionGroup::User)).await;
+        assert_eq!(response.status, ResponseStatus::Ok);
+        assert!(matches!(
+            response.data,
+            ActionResponses::ListInstances { .. }
+        ));
+
+        let response = process(&v1, raw, &context(PermissionGroup::Custom)).await;
+        assert_eq!(response.status, ResponseStatus::Error);
+    }
+
+    #[tokio::test]
+    async fn daemon_info_reports_instance_count() {
+        let (tx, _rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        let raw = r#"{"action": "get_daemon_info", "params": {}}"#;
+
+        let response = process(&v1, raw, &context(PermissionGroup::User)).await;
+        assert_eq!(response.status, ResponseStatus::Ok);
+        let data = serde_json::to_value(&response.data).unwrap();
+        assert_eq!(data["protocols"], serde_json::json!(["v1"]));
+        assert_eq!(data["api_version"], API_VERSION);
+
+        let root = crate::utils::temp_path("instances");
+        for _ in 0..2 {
+            std::fs::create_dir_all(root.join(Uuid::new_v4().to_string())).unwrap();
+        }
+        std::fs::create_dir_all(root.join("not-an-instance")).unwrap();
+        let data = serde_json::to_value(v1.daemon_info_in(&root).await).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(data["instance_count"], 2);
+    }
+
+    #[tokio::test]
+    async fn file_actions_are_ordered_by_file_id() {
+        let (tx, _rx) = unbounded_channel();
+        let file_id = Uuid::new_v4();
+        let raw = format!(
+            r#"{{"action": "file_upload_chunk", "params": {{"file_id": "{}", "offset": 0, "data": ""}}}}"#,
+            file_id
+        );
+
+        let v1 = protocol(ProtocolV1Config::default(), tx.clone()).await;
+        assert_eq!(v1.ordering_key(&raw), Some(file_id));
+        assert_eq!(v1.ordering_key(r#"{"action": "ping", "params": {}}"#), None);
+
+        let config = ProtocolV1Config {
+            ordered_file_actions: false,
+            ..Default::default()
+        };
+        assert_eq!(protocol(config, tx).await.ordering_key(&raw), None);
+    }
+
+    #[tokio::test]
+    async fn list_instances_takes_filters() {
+        let root = crate::utils::temp_path("instances");
+        for (name, group) in [("survival", Some("main")), ("creative", None)] {
+            let uuid = Uuid::new_v4();
+            let dir = root.join(uuid.to_string());
+            std::fs::create_dir_all(&dir).unwrap();
+            let config = json!({
+                "uuid": uuid,
+                "input_encoding": "utf-8",
+                "output_encoding": "utf-8",
+                "working_directory": dir,
+                "java_args": [],
+                "java_path": "java",
+                "name": name,
+                "instance_type": "vanilla",
+                "target": "server.jar",
+                "target_type": "jar",
+                "tags": ["public"],
+                "group": group,
+            });
+            std::fs::write(dir.join("daemon_instance.json"), config.to_string()).unwrap();
+        }
+
+        let names = |params: serde_json::Value| {
+            let raw = json!({"action": "list_instances", "params": params}).to_string();
+            let root = &root;
+            async move {
+                let ActionRequests::ListInstances { filter } =
+                    ProtocolV1::parse(&raw).unwrap().request
+                else {
+                    panic!("unexpected request: {}", raw);
+                };
+                let response = ProtocolV1::list_instances_handler(root, filter)
+                    .await
+                    .unwrap();
+                let ActionResponses::ListInstances { instances } = response else {
+                    panic!("unexpected response: {:?}", response);
+                };
+                instances.into_iter().map(|i| i.name).collect::<Vec<_>>()
+            }
+        };
+        let listed = [
+            names(json!({})).await,
+            names(json!({"tag": "public", "group": "main"})).await,
+            names(json!({"group": "other"})).await,
+        ];
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(listed[0], ["creative", "survival"]);
+        assert_eq!(listed[1], ["survival"]);
+        assert!(listed[2].is_empty());
+    }
+
+    #[tokio::test]
+    async fn data_root_is_listed() {
+        let (tx, _rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        let ctx = context(PermissionGroup::Admin);
+        std::fs::create_dir_all("daemon").unwrap();
+
+        let raw = r#"{"action": "file_list_directory", "params": {"path": ""}}"#;
+        let response = process(&v1, raw, &ctx).await;
+        assert_eq!(response.status, ResponseStatus::Ok);
+        let raw = r#"{"action": "file_list_directory", "params": {"path": "daemon/.."}}"#;
+        match process(&v1, raw, &ctx).await.data {
+            ActionResponses::ActionError { error_code, .. } => {
+                assert_eq!(error_code, Some(ErrorCode::PathNotAllowed))
+            }
+            data => panic!("expected an error, got {:?}", data),
+        }
+    }
+
+    #[tokio::test]
+    async fn downloads_are_closed_with_the_connection() {
+        let (tx, _rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        let ctx = context(PermissionGroup::Admin);
+        let path = format!("daemon/test-{}.bin", Uuid::new_v4());
+        std::fs::create_dir_all("daemon").unwrap();
+        std::fs::write(&path, b"abcd").unwrap();
+
+        let raw = format!(
+            r#"{{"action": "file_download_request", "params": {{"path": "{}"}}}}"#,
+            path
+        );
+        let response = process(&v1, &raw, &ctx).await;
+        assert_eq!(response.status, ResponseStatus::Ok);
+        assert_eq!(v1.files.session_stats(), (0, 1));
+
+        v1.close_downloads(&ctx).await;
+        assert_eq!(v1.files.session_stats(), (0, 0));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// split a binary response frame into the response and the content
+    fn split_frame(frame: &[u8]) -> (serde_json::Value, &[u8]) {
+        let len = u32::from_be_bytes(frame[..4].try_into().unwrap()) as usize;
+        let header = serde_json::from_slice(&frame[4..4 + len]).unwrap();
+        (header, &frame[4 + len..])
+    }
+
+    #[tokio::test]
+    async fn raw_download_round_trips_uploaded_file() {
+        use sha1::{Digest, Sha1};
+
+        let (tx, _rx) = unbounded_channel();
+        let v1 = protocol(ProtocolV1Config::default(), tx).await;
+        let ctx = context(PermissionGroup::Admin);
+        let path = format!("daemon/test-{}.bin", Uuid::new_v4());
+        // every byte value that is not part of a utf16 surrogate, so it can be uploaded
+        let content: Vec<u8> = (0..=255u8).filter(|b| !(0xd8..=0xdf).contains(b)).collect();
+        let sha1 = format!("{:x}", Sha1::digest(&content));
+        let data = String::from_utf16(
+            &content
+                .chunks(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect::<Vec<u16>>(),
+        )
+        .unwrap();
+
+        let raw = json!({
+            "action": "file_upload_request",
+            "params": {"path": path, "sha1": sha1, "chunk_size": content.len(), "size": content.len()},
+        });
+        let response = process(&v1, &raw.to_string(), &ctx).await;
+        let ActionResponses::FileUploadRequest { file_id } = response.data else {
+            panic!("unexpected response: {:?}", response.data);
+        };
+        let raw = json!({
+            "action": "file_upload_chunk",
+            "params": {"file_id": file_id, "offset": 0, "data": data},
+        });
+        let response = process(&v1, &raw.to_string(), &ctx).await;
+        assert_eq!(response.status, ResponseStatus::Ok);
+
+        let raw = json!({"action": "file_download_request", "params": {"path": path}});
+        let response = process(&v1, &raw.to_string(), &ctx).await;
+        let ActionResponses::FileDownloadRequest { file_id, .. } = response.data else {
+            panic!("unexpected response: {:?}", response.data);
+        };
+
+        // odd sized ranges come back without padding
+        let mut downloaded = vec![];
+        let mut rolling = None;
+        for range in ["0..101", "101.."] {
+            let raw = json!({
+                "action": "file_download_range_raw",
+                "params": {"file_id": file_id, "range": range},
+                "echo": range,
+            });
+            let frame = v1
+                .process_binary(raw.to_string().as_bytes(), &ctx)
+                .await
+                .unwrap();
+            let (header, bytes) = split_frame(&frame);
+            assert_eq!(header["status"], "ok");
+            assert_eq!(header["echo"], range);
+            assert_eq!(header["data"]["size"], bytes.len());
+            downloaded.extend_from_slice(bytes);
+            rolling = header["data"]["sha1"].as_str().map(str::to_string);
+        }
+        assert_eq!(format!("{:x}", Sha1::digest(&downloaded)), sha1);
+        assert_eq!(rolling, Some(sha1));
+
+        // text frames can not carry the raw content
+        let raw = json!({
+            "action": "file_download_range_raw",
+            "params": {"file_id": file_id, "range": "0..1"},
+        });
+        let response = process(&v1, &raw.to_string(), &ctx).await;
+        assert_eq!(response.status, ResponseStatus::Error);
+
+        v1.close_downloads(&ctx).await;
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,8 +1,9 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{drivers::DriversConfig, protocols::ProtocolConfig};
+use crate::{drivers::DriversConfig, protocols::ProtocolConfig, user::AuthConfig};
 
 use super::file::{Config, FileIoWithBackup};
+use super::java::JavaConfig;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// immutable through full lifetime of app, unless restart app.
@@ -10,6 +11,10 @@ use super::file::{Config, FileIoWithBackup};
 pub struct AppConfig {
     pub drivers: DriversConfig,
     pub protocols: ProtocolConfig,
+    #[serde(default)]
+    pub java: JavaConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
 }
 
 impl FileIoWithBackup for AppConfig {}
@@ -0,0 +1,129 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use log::{debug, warn};
+use serde::Deserialize;
+
+/// kinds of data the daemon accumulates over time, each lives under its own root
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageCategory {
+    Logs,
+    Crashes,
+    Backups,
+}
+
+impl StorageCategory {
+    pub fn root(&self) -> &'static str {
+        match self {
+            StorageCategory::Logs => "daemon/logs",
+            StorageCategory::Crashes => "daemon/crashes",
+            StorageCategory::Backups => "daemon/backups",
+        }
+    }
+}
+
+/// what a cleanup removed
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CleanupReport {
+    pub removed_files: u64,
+    pub reclaimed_bytes: u64,
+}
+
+/// remove the files of `categories` last modified more than `older_than` ago
+pub async fn cleanup(
+    categories: &[StorageCategory],
+    older_than: Duration,
+) -> anyhow::Result<CleanupReport> {
+    let roots = categories.iter().map(|c| c.root().into()).collect();
+    cleanup_roots(roots, older_than).await
+}
+
+async fn cleanup_roots(roots: Vec<PathBuf>, older_than: Duration) -> anyhow::Result<CleanupReport> {
+    let threshold = SystemTime::now()
+        .checked_sub(older_than)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    tokio::task::spawn_blocking(move || {
+        let mut report = CleanupReport::default();
+        for root in roots {
+            if root.exists() {
+                cleanup_dir(&root, threshold, &mut report);
+            }
+        }
+        report
+    })
+    .await
+    .map_err(Into::into)
+}
+
+/// symlinks are removed as links and never followed, so nothing outside the root is touched
+fn cleanup_dir(dir: &Path, threshold: SystemTime, report: &mut CleanupReport) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("could not read {}: {}", dir.display(), err);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            cleanup_dir(&path, threshold, report);
+            continue;
+        }
+        if metadata.modified().is_ok_and(|m| m >= threshold) {
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                debug!("cleaned up {}", path.display());
+                report.removed_files += 1;
+                report.reclaimed_bytes += metadata.len();
+            }
+            Err(err) => warn!("could not remove {}: {}", path.display(), err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn only_old_files_are_removed() {
+        let root = format!("daemon/test-{}", Uuid::new_v4());
+        std::fs::create_dir_all(format!("{}/2024", root)).unwrap();
+        let old = format!("{}/2024/old.log.gz", root);
+        let new = format!("{}/latest.log", root);
+        std::fs::write(&old, [0; 64]).unwrap();
+        std::fs::write(&new, [0; 16]).unwrap();
+        File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(40 * 24 * 3600))
+            .unwrap();
+
+        let report = cleanup_roots(
+            vec![root.clone().into()],
+            Duration::from_secs(30 * 24 * 3600),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            report,
+            CleanupReport {
+                removed_files: 1,
+                reclaimed_bytes: 64
+            }
+        );
+        assert!(!Path::new(&old).exists());
+        assert!(Path::new(&new).exists());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
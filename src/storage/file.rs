@@ -1,5 +1,6 @@
 use crate::utils::U64Remain;
-use std::path::Path;
+use sha1::{Digest, Sha1};
+use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
@@ -55,6 +56,91 @@ pub trait Config: FileIoWithBackup {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgo {
+    Sha1,
+    Sha256,
+}
+
+/// a computed digest, valid as long as the file's mtime and size are unchanged
+pub struct CachedDigest {
+    pub modified: std::time::SystemTime,
+    pub size: u64,
+    pub digest: String,
+}
+
+/// what is known about a file without opening it
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct FileMeta {
+    pub size: u64,
+    /// unix millis, `None` where the platform does not record it
+    pub created: Option<u64>,
+    pub modified: Option<u64>,
+    pub accessed: Option<u64>,
+    pub readonly: bool,
+    /// dot files, or the hidden attribute on windows
+    pub hidden: bool,
+}
+
+impl FileMeta {
+    pub fn from_metadata(metadata: &std::fs::Metadata, name: &str) -> Self {
+        let millis = |time: std::io::Result<std::time::SystemTime>| {
+            time.ok()?
+                .duration_since(std::time::UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_millis() as u64)
+        };
+        #[cfg(windows)]
+        let hidden = {
+            use std::os::windows::fs::MetadataExt;
+            const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+            metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+        };
+        #[cfg(not(windows))]
+        let hidden = name.starts_with('.');
+        #[cfg(windows)]
+        let _ = name;
+
+        Self {
+            size: metadata.len(),
+            created: millis(metadata.created()),
+            modified: millis(metadata.modified()),
+            accessed: millis(metadata.accessed()),
+            readonly: metadata.permissions().readonly(),
+            hidden,
+        }
+    }
+}
+
+/// a file or directory in a [`DirectoryEntry`]
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct EntryInfo {
+    pub name: String,
+    #[serde(flatten)]
+    pub meta: FileMeta,
+}
+
+/// content of a directory, entries sorted by name
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    /// path to list to go up, `None` at the data root
+    pub parent: Option<String>,
+    pub files: Vec<EntryInfo>,
+    pub directories: Vec<EntryInfo>,
+}
+
+/// [`FileMeta`] along with the digests cached for the file, none are computed for it
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct FileStat {
+    #[serde(flatten)]
+    pub meta: FileMeta,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha1: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
 // FileLoadInfo 类似父类
 pub struct FileLoadInfo {
     pub size: u64,
@@ -76,9 +162,45 @@ impl FileLoadInfo {
     }
 }
 
+/// sha1 of the bytes of a file seen so far, only kept while they arrive sequentially
+pub struct RollingSha1 {
+    hasher: Option<Sha1>,
+    offset: u64,
+}
+
+impl RollingSha1 {
+    pub fn new() -> Self {
+        Self {
+            hasher: Some(Sha1::new()),
+            offset: 0,
+        }
+    }
+
+    /// feed the bytes at `offset`, returns the digest of all bytes seen so far
+    /// if they are still sequential.
+    pub fn update(&mut self, offset: u64, data: &[u8]) -> Option<String> {
+        if offset != self.offset {
+            self.hasher = None;
+        }
+        let hasher = self.hasher.as_mut()?;
+        hasher.update(data);
+        self.offset += data.len() as u64;
+        Some(format!("{:x}", hasher.clone().finalize()))
+    }
+
+    /// the sha1 of the whole file, if all `size` bytes were seen sequentially
+    pub fn finish(&mut self, size: u64) -> Option<String> {
+        let hasher = self.hasher.take()?;
+        (self.offset == size).then(|| format!("{:x}", hasher.finalize()))
+    }
+}
+
 pub struct FileUploadInfo {
     pub base: FileLoadInfo,
     pub chunk_size: u64,
+    /// where chunks are written until the upload completes
+    pub tmp_path: PathBuf,
+    pub rolling_sha1: RollingSha1,
 }
 
 impl FileUploadInfo {
@@ -88,22 +210,27 @@ impl FileUploadInfo {
         file: tokio::fs::File,
         sha1: Option<String>,
         chunk_size: u64,
+        tmp_path: PathBuf,
     ) -> Self {
         Self {
             base: FileLoadInfo::new(size, path, file, sha1),
             chunk_size,
+            tmp_path,
+            rolling_sha1: RollingSha1::new(),
         }
     }
 }
 
 pub struct FileDownloadInfo {
     pub base: FileLoadInfo,
+    pub rolling_sha1: RollingSha1,
 }
 
 impl FileDownloadInfo {
     pub fn new(size: u64, path: String, file: tokio::fs::File, sha1: Option<String>) -> Self {
         Self {
             base: FileLoadInfo::new(size, path, file, sha1),
+            rolling_sha1: RollingSha1::new(),
         }
     }
 }
@@ -1,12 +1,18 @@
 use crate::protocols::ProtocolConfig;
-use std::io::Read;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::storage::file::{FileDownloadInfo, FileUploadInfo};
+use crate::storage::file::{
+    CachedDigest, DirectoryEntry, EntryInfo, FileDownloadInfo, FileMeta, FileStat, FileUploadInfo,
+    HashAlgo,
+};
 use anyhow::{anyhow, bail};
-use log::debug;
+use log::{debug, warn};
 use sha1::{Digest, Sha1};
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+use tokio::sync::Mutex;
 
 use scc::HashMap;
 use uuid::Uuid;
@@ -14,12 +20,60 @@ use uuid::Uuid;
 const ROOT: &str = "daemon";
 const DOWNLOAD_ROOT: &str = "daemon/downloads";
 
+/// the data disk can not take writes, retrying will not help until an admin steps in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskError {
+    Full,
+    ReadOnly,
+}
+
+impl DiskError {
+    pub fn from_io(err: &std::io::Error) -> Option<Self> {
+        match err.kind() {
+            ErrorKind::StorageFull | ErrorKind::QuotaExceeded => Some(DiskError::Full),
+            ErrorKind::ReadOnlyFilesystem => Some(DiskError::ReadOnly),
+            _ => None,
+        }
+    }
+
+    /// `err` as a [`DiskError`] if it is one, unchanged otherwise
+    pub fn map(err: anyhow::Error) -> anyhow::Error {
+        match err.downcast_ref::<std::io::Error>().and_then(Self::from_io) {
+            Some(disk_error) => disk_error.into(),
+            None => err,
+        }
+    }
+}
+
+impl std::fmt::Display for DiskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DiskError::Full => "the data disk is full or over quota, free some space and retry",
+            DiskError::ReadOnly => {
+                "the data disk is read-only, check how the daemon directory is mounted"
+            }
+        })
+    }
+}
+
+impl std::error::Error for DiskError {}
+
+/// an open download, reads and close take turns on its file
+struct DownloadSession {
+    path: String,
+    size: u64,
+    // `None` once closed
+    info: Mutex<Option<FileDownloadInfo>>,
+}
+
 pub struct Files {
     protocol_config: ProtocolConfig,
     // use ahash to speed up ops
     upload_sessions: HashMap<Uuid, FileUploadInfo, ahash::RandomState>,
     // use ahash to speed up ops
-    download_sessions: HashMap<Uuid, FileDownloadInfo, ahash::RandomState>,
+    download_sessions: HashMap<Uuid, Arc<DownloadSession>, ahash::RandomState>,
+    // digests keyed by normalized path, invalidated by mtime+size
+    digest_cache: HashMap<(String, HashAlgo), CachedDigest, ahash::RandomState>,
 }
 
 // files utils
@@ -29,9 +83,15 @@ impl Files {
             protocol_config,
             upload_sessions: HashMap::default(),
             download_sessions: HashMap::default(),
+            digest_cache: HashMap::default(),
         }
     }
 
+    /// whether `path` stays under the data root, without touching the file system
+    pub fn is_in_root(path: &str) -> bool {
+        Self::validate_path(path, ROOT)
+    }
+
     // 算法层面，判断path是否在root下
     fn validate_path(path: &str, root: &str) -> bool {
         let normalized_path = Self::normalize_path(path);
@@ -63,25 +123,205 @@ impl Files {
         })
     }
 
+    fn validate_upload_size(&self, size: u64, chunk_size: u64) -> anyhow::Result<()> {
+        let config = &self.protocol_config.v1;
+        if size > config.max_upload_size {
+            bail!(
+                "file too large: {} bytes, max {} bytes",
+                size,
+                config.max_upload_size
+            );
+        }
+        if chunk_size == 0 {
+            bail!("chunk size must not be 0");
+        }
+        // an empty file still takes one (empty) chunk to complete
+        if chunk_size > size && size > 0 {
+            bail!("chunk size {} larger than file size {}", chunk_size, size);
+        }
+        if chunk_size > config.max_chunk_size {
+            bail!(
+                "chunk size too large: {} bytes, max {} bytes",
+                chunk_size,
+                config.max_chunk_size
+            );
+        }
+        // a single chunk covering a small file is fine
+        if chunk_size < config.min_chunk_size && chunk_size < size {
+            bail!(
+                "chunk size too small: {} bytes, min {} bytes",
+                chunk_size,
+                config.min_chunk_size
+            );
+        }
+        Ok(())
+    }
+
+    fn upload_tmp_path(&self, file_id: Uuid) -> PathBuf {
+        Path::new(&self.protocol_config.v1.upload_temp_dir).join(format!("{}.tmp", file_id))
+    }
+
+    /// move a finished upload to its destination, falls back to copy+fsync across volumes
+    async fn move_file(from: &Path, to: &str) -> anyhow::Result<()> {
+        match tokio::fs::rename(from, to).await {
+            Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                warn!(
+                    "upload temp dir is on a different volume than {}, falling back to copy",
+                    to
+                );
+                tokio::fs::copy(from, to).await?;
+                File::options()
+                    .write(true)
+                    .open(to)
+                    .await?
+                    .sync_all()
+                    .await?;
+                tokio::fs::remove_file(from).await?;
+                Ok(())
+            }
+            res => Ok(res?),
+        }
+    }
+
+    /// write and delete a temp file under ROOT
+    pub async fn check_writable() -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(ROOT).await?;
+        let path = format!("{}/.self-test-{}", ROOT, Uuid::new_v4());
+        tokio::fs::write(&path, b"ok").await?;
+        tokio::fs::remove_file(&path).await?;
+        Ok(())
+    }
+
+    /// (upload sessions, download sessions)
+    pub fn session_stats(&self) -> (usize, usize) {
+        (self.upload_sessions.len(), self.download_sessions.len())
+    }
+
+    /// every open session holds a file descriptor, refuse new ones past the global cap
+    fn check_session_cap(&self) -> anyhow::Result<()> {
+        let (uploads, downloads) = self.session_stats();
+        if uploads + downloads >= self.protocol_config.max_file_sessions {
+            bail!("too many sessions");
+        }
+        Ok(())
+    }
+
+    #[cfg(any(test, feature = "session_leak_check"))]
+    pub fn has_session(&self, file_id: &Uuid) -> bool {
+        self.upload_sessions.contains(file_id) || self.download_sessions.contains(file_id)
+    }
+
     pub async fn get_sha1(path: &str) -> anyhow::Result<String> {
+        Self::compute_digest(path, HashAlgo::Sha1).await
+    }
+
+    async fn compute_digest(path: &str, algo: HashAlgo) -> anyhow::Result<String> {
         let path = path.to_string();
-        tokio::task::spawn_blocking(|| -> anyhow::Result<String> {
-            let mut hasher = Sha1::new();
+        tokio::task::spawn_blocking(move || -> anyhow::Result<String> {
+            let mut sha1 = Sha1::new();
+            let mut sha256 = ring::digest::Context::new(&ring::digest::SHA256);
             let mut file = std::fs::File::options().read(true).open(path)?;
             let mut buffer = [0; 32768];
             loop {
-                let read = file.read(&mut buffer);
-                if read.is_err() {
+                let read = file.read(&mut buffer)?;
+                if read == 0 {
                     break;
                 }
-                hasher.update(&buffer[..read.unwrap()]);
+                match algo {
+                    HashAlgo::Sha1 => sha1.update(&buffer[..read]),
+                    HashAlgo::Sha256 => sha256.update(&buffer[..read]),
+                }
             }
-            Ok(format!("{:x}", hasher.finalize()))
+            Ok(match algo {
+                HashAlgo::Sha1 => format!("{:x}", sha1.finalize()),
+                HashAlgo::Sha256 => sha256
+                    .finish()
+                    .as_ref()
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect(),
+            })
         })
         .await
         .unwrap() // unwarp is safe: won't cancel and panic
     }
 
+    /// digest of a file under ROOT, cached until its mtime or size changes
+    pub async fn get_digest(&self, path: &str, algo: HashAlgo) -> anyhow::Result<String> {
+        if !Self::validate_path(path, ROOT) {
+            bail!("invalid path");
+        }
+        let metadata = tokio::fs::metadata(path).await?;
+        if !metadata.is_file() {
+            bail!("not a file");
+        }
+        let modified = metadata.modified()?;
+        let size = metadata.len();
+        let key = (Self::normalize_path(path), algo);
+
+        if let Some(digest) = self.cached_digest(&key, &metadata).await {
+            return Ok(digest);
+        }
+
+        let digest = Self::compute_digest(path, algo).await?;
+        self.digest_cache
+            .upsert_async(
+                key,
+                CachedDigest {
+                    modified,
+                    size,
+                    digest: digest.clone(),
+                },
+            )
+            .await;
+        Ok(digest)
+    }
+
+    /// the cached digest, if the file has not changed since
+    async fn cached_digest(
+        &self,
+        key: &(String, HashAlgo),
+        metadata: &std::fs::Metadata,
+    ) -> Option<String> {
+        let modified = metadata.modified().ok()?;
+        let size = metadata.len();
+        self.digest_cache
+            .read_async(key, |_, v| {
+                (v.modified == modified && v.size == size).then(|| v.digest.clone())
+            })
+            .await
+            .flatten()
+    }
+
+    /// metadata of a file under ROOT, without opening it or computing digests
+    pub async fn stat(&self, path: &str) -> anyhow::Result<FileStat> {
+        if !Self::validate_path(path, ROOT) {
+            bail!("invalid path");
+        }
+        let metadata = match tokio::fs::metadata(path).await {
+            Err(e) if e.kind() == ErrorKind::NotFound => bail!("file not found"),
+            metadata => metadata?,
+        };
+        if !metadata.is_file() {
+            bail!("not a file");
+        }
+
+        let name = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        let normalized = Self::normalize_path(path);
+        Ok(FileStat {
+            meta: FileMeta::from_metadata(&metadata, &name),
+            sha1: self
+                .cached_digest(&(normalized.clone(), HashAlgo::Sha1), &metadata)
+                .await,
+            sha256: self
+                .cached_digest(&(normalized, HashAlgo::Sha256), &metadata)
+                .await,
+        })
+    }
+
     /// encode bytes to utf16 string
     fn bytes_to_string_data(mut bytes: Vec<u8>) -> String {
         if bytes.len() % 2 != 0 {
@@ -107,9 +347,11 @@ impl Files {
         chunk_size: u64,
         sha1: Option<&str>,
     ) -> anyhow::Result<Uuid> {
-        if path.is_some_and(|p| Self::validate_path(p, ROOT)) {
+        if path.is_some_and(|p| !Self::validate_path(p, ROOT)) {
             bail!("invalid path");
         }
+        self.validate_upload_size(size, chunk_size)?;
+        self.check_session_cap()?;
         let path = path.unwrap_or(DOWNLOAD_ROOT);
 
         // check if uploading, prevent extra io operation
@@ -121,23 +363,35 @@ impl Files {
             bail!("file is uploading");
         }
 
-        let tmp_file = path.to_string() + ".tmp";
+        let uuid = Uuid::new_v4();
+        let tmp_path = self.upload_tmp_path(uuid);
+        let modes = &self.protocol_config.v1.file_modes;
+        modes
+            .create_dir_all(&self.protocol_config.v1.upload_temp_dir)
+            .await
+            .map_err(|e| DiskError::map(e.into()))?;
 
-        let file = File::options()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(tmp_file)
-            .await?;
-        file.set_len(size).await?;
+        let mut options = File::options();
+        options.create(true).truncate(true).write(true);
+        #[cfg(unix)]
+        options.mode(modes.file.0);
+        let file = options
+            .open(&tmp_path)
+            .await
+            .map_err(|e| DiskError::map(e.into()))?;
+        if let Err(e) = file.set_len(size).await {
+            drop(file);
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(DiskError::map(e.into()));
+        }
 
-        let uuid = Uuid::new_v4();
         let info = FileUploadInfo::new(
             size,
             path.to_string(),
             file,
             sha1.map(|v| v.to_string()),
             chunk_size,
+            tmp_path,
         );
         if self.upload_sessions.insert_async(uuid, info).await.is_err() {
             bail!("file is uploading");
@@ -147,12 +401,16 @@ impl Files {
         Ok(uuid)
     }
 
+    /// write a chunk, returns (done, received, rolling sha1 of received bytes if sequential).
+    ///
+    /// if `chunk_sha1` is given, the chunk is rejected without being written when it mismatches.
     pub async fn upload_chunk(
         &self,
         file_id: Uuid,
         offset: u64,
         data: String,
-    ) -> anyhow::Result<(bool, u64)> {
+        chunk_sha1: Option<&str>,
+    ) -> anyhow::Result<(bool, u64, Option<String>)> {
         // parse string data to bytes ()
         let data: Vec<u16> = data.encode_utf16().collect();
         // convert vec<u16> to big endian bytes
@@ -163,7 +421,7 @@ impl Files {
         }
         self.upload_sessions
             .read_async(&file_id, |_, v| {
-                if offset >= v.base.size {
+                if offset >= v.base.size && offset > 0 {
                     bail!("offset out of range");
                 }
                 Ok(())
@@ -179,22 +437,39 @@ impl Files {
             }
             let mut session_info = session_info.unwrap();
             let chunk_size = session_info.chunk_size as usize;
+            // bytes past the end of the file (e.g. utf16 padding) are dropped
+            let left = (session_info.base.size - offset) as usize;
+            let data = &data[..chunk_size.min(left).min(data.len())];
+
+            if let Some(chunk_sha1) = chunk_sha1 {
+                if format!("{:x}", Sha1::digest(data)) != chunk_sha1.to_lowercase() {
+                    bail!("chunk sha1 mismatch at offset {}", offset);
+                }
+            }
+
             let file = &mut session_info.base.file;
-            file.seek(SeekFrom::Start(offset)).await?;
-            file.write_all(&data[..std::cmp::min(chunk_size, data.len())])
-                .await?;
+            let written = async {
+                file.seek(SeekFrom::Start(offset)).await?;
+                file.write_all(data).await
+            }
+            .await;
+            if let Err(e) = written {
+                drop(session_info);
+                return Err(self.fail_upload(file_id, e.into()).await);
+            }
 
             // update info
             session_info
                 .base
                 .remain
                 .reduce(offset, offset + data.len() as u64);
+            let rolling_sha1 = session_info.rolling_sha1.update(offset, data);
 
             let remain = session_info.base.remain.get_remain();
 
             if remain > 0 {
                 // partial upload
-                return Ok((false, session_info.base.size - remain));
+                return Ok((false, session_info.base.size - remain, rolling_sha1));
             }
         }
 
@@ -206,20 +481,54 @@ impl Files {
         // complete upload
         let path = session_info.base.path.clone();
         let sha1 = session_info.base.sha1.take();
-        session_info.base.file.sync_all().await?;
-        // move file
-        tokio::fs::rename(path.clone() + ".tmp", &path).await?;
+        let size = session_info.base.size;
+        let rolling_sha1 = session_info.rolling_sha1.finish(size);
+        let synced = session_info.base.file.sync_all().await;
+        let tmp_path = session_info.tmp_path.clone();
         drop(session_info); //close file
+        if let Err(e) = match synced {
+            Ok(()) => Self::move_file(&tmp_path, &path).await,
+            Err(e) => Err(e.into()),
+        } {
+            // the session is gone, nothing would clean up after it
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(DiskError::map(e));
+        }
 
+        // the umask applied when the tmp file was created
+        if let Err(e) = self
+            .protocol_config
+            .v1
+            .file_modes
+            .apply_to_file(&path)
+            .await
+        {
+            warn!("could not set the mode of {}: {}", path, e);
+        }
         debug!("upload finished: {}", &path);
         if let Some(sha1) = sha1 {
-            let calculated_sha1 = Self::get_sha1(&path).await?;
+            // sequential uploads were hashed on the fly, no need to read the file again
+            let calculated_sha1 = match rolling_sha1.clone() {
+                Some(rolling_sha1) => rolling_sha1,
+                None => Self::get_sha1(&path).await?,
+            };
 
             if sha1 != calculated_sha1 {
                 bail!("sha1 mismatch");
             }
         }
-        Ok((true, 0))
+        Ok((true, 0, rolling_sha1))
+    }
+
+    /// a write to an upload failed, uploads that can not go on once the disk is full
+    /// or read-only are cancelled
+    async fn fail_upload(&self, file_id: Uuid, err: anyhow::Error) -> anyhow::Error {
+        let err = DiskError::map(err);
+        if let Some(disk_error) = err.downcast_ref::<DiskError>() {
+            warn!("upload {} cancelled: {:?}", file_id, disk_error);
+            self.upload_cancel(file_id).await;
+        }
+        err
     }
 
     pub async fn upload_cancel(&self, file_id: Uuid) -> bool {
@@ -231,7 +540,7 @@ impl Files {
         {
             drop(session_info.base.file); // close file
                                           // delete tmp file
-            let _ = tokio::fs::remove_file(session_info.base.path.clone() + ".tmp").await;
+            let _ = tokio::fs::remove_file(&session_info.tmp_path).await;
             debug!("upload file cancelled: {}", session_info.base.path);
             true
         } else {
@@ -240,6 +549,97 @@ impl Files {
     }
 }
 
+impl Files {
+    /// delete a file under ROOT, directories only if `recursive`
+    pub async fn delete(&self, path: &str, recursive: bool) -> anyhow::Result<()> {
+        if !Self::validate_path(path, ROOT) {
+            bail!("invalid path");
+        }
+        if Self::normalize_path(path) == Self::normalize_path(ROOT) {
+            bail!("the data root can not be deleted");
+        }
+        if self.in_use(path) {
+            bail!("file is in use by an upload or download");
+        }
+
+        let metadata = match tokio::fs::symlink_metadata(path).await {
+            Err(e) if e.kind() == ErrorKind::NotFound => bail!("file not found"),
+            metadata => metadata?,
+        };
+        let removed = if metadata.is_dir() {
+            if !recursive {
+                bail!("{} is a directory, set recursive to delete it", path);
+            }
+            tokio::fs::remove_dir_all(path).await
+        } else {
+            tokio::fs::remove_file(path).await
+        };
+        removed.map_err(|e| DiskError::map(e.into()))?;
+        debug!("deleted: {}", path);
+        Ok(())
+    }
+
+    /// list a directory under ROOT, an empty path lists ROOT itself
+    pub async fn list_directory(&self, path: &str) -> anyhow::Result<DirectoryEntry> {
+        let path = if path.is_empty() { ROOT } else { path };
+        if !Self::validate_path(path, ROOT) {
+            bail!("invalid path");
+        }
+
+        let mut entries = match tokio::fs::read_dir(path).await {
+            Err(e) if e.kind() == ErrorKind::NotFound => bail!("directory not found"),
+            Err(e) if e.kind() == ErrorKind::NotADirectory => bail!("not a directory"),
+            entries => entries?,
+        };
+        let (mut files, mut directories) = (vec![], vec![]);
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // gone since it was listed
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            let info = EntryInfo {
+                meta: FileMeta::from_metadata(&metadata, &name),
+                name,
+            };
+            if metadata.is_dir() {
+                directories.push(info);
+            } else {
+                files.push(info);
+            }
+        }
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        directories.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let normalized = Self::normalize_path(path);
+        let parent = (normalized != Self::normalize_path(ROOT)).then(|| {
+            let normalized = normalized.trim_end_matches('/');
+            normalized
+                .rsplit_once('/')
+                .map_or(ROOT, |(parent, _)| parent)
+                .to_string()
+        });
+        Ok(DirectoryEntry {
+            parent,
+            files,
+            directories,
+        })
+    }
+
+    /// whether a session has `path`, or a file under it, open
+    fn in_use(&self, path: &str) -> bool {
+        let target = Self::normalize_path(path);
+        // component-wise, `daemon/test` does not cover `daemon/test2`
+        let under = |path: &str| Path::new(&Self::normalize_path(path)).starts_with(&target);
+        let mut in_use = false;
+        self.upload_sessions.scan(|_, v| {
+            in_use |= under(&v.base.path) || under(&v.tmp_path.to_string_lossy());
+        });
+        self.download_sessions.scan(|_, v| in_use |= under(&v.path));
+        in_use
+    }
+}
+
 // download operations
 impl Files {
     pub async fn download_request(&self, path: &str) -> anyhow::Result<(Uuid, u64, String)> {
@@ -247,29 +647,39 @@ impl Files {
             bail!("invalid path");
         }
 
-        if tokio::fs::try_exists(path).await? {
+        if !tokio::fs::try_exists(path).await? {
             bail!("file not found");
         }
 
         let mut file_sessions = 0u8;
         // use sync version
         self.download_sessions.scan(|_, v| {
-            if v.base.path == path {
+            if v.path == path {
                 file_sessions += 1;
             }
         });
-        if file_sessions > self.protocol_config.v1.file_download_sessions {
+        if file_sessions >= self.protocol_config.v1.file_download_sessions {
             bail!("max download sessions of file '{}' reached", path);
         }
+        self.check_session_cap()?;
 
         let sha1 = Self::get_sha1(path).await?;
         let file = File::options().read(true).open(path).await?;
         let size = file.metadata().await.map(|m| m.len())?;
         let id = Uuid::new_v4();
-        let session_info = FileDownloadInfo::new(size, path.to_string(), file, Some(sha1.clone()));
+        let session = DownloadSession {
+            path: path.to_string(),
+            size,
+            info: Mutex::new(Some(FileDownloadInfo::new(
+                size,
+                path.to_string(),
+                file,
+                Some(sha1.clone()),
+            ))),
+        };
         if self
             .download_sessions
-            .insert_async(id, session_info)
+            .insert_async(id, Arc::new(session))
             .await
             .is_err()
         {
@@ -279,37 +689,610 @@ impl Files {
         Ok((id, size, sha1))
     }
 
-    pub async fn download_range(&self, id: Uuid, from: u64, to: u64) -> anyhow::Result<String> {
-        if !self
-            .download_sessions
-            .read_async(&id, |_, v| to <= v.base.size && from < to)
-            .await
-            .unwrap_or(false)
-        {
-            bail!("invalid download file id or invalid range");
-        }
+    /// size of the file an open download serves, `None` if there is no such download
+    pub async fn download_size(&self, id: Uuid) -> Option<u64> {
+        self.download_sessions.read_async(&id, |_, v| v.size).await
+    }
 
-        let mut entry = self
+    /// read a range, returns (content, rolling sha1 of bytes served so far if sequential)
+    pub async fn download_range(
+        &self,
+        id: Uuid,
+        from: u64,
+        to: u64,
+    ) -> anyhow::Result<(String, Option<String>)> {
+        let (buf, rolling_sha1) = self.download_range_raw(id, from, to).await?;
+        Ok((Self::bytes_to_string_data(buf), rolling_sha1))
+    }
+
+    /// same as [`Files::download_range`], with the bytes as they are
+    pub async fn download_range_raw(
+        &self,
+        id: Uuid,
+        from: u64,
+        to: u64,
+    ) -> anyhow::Result<(Vec<u8>, Option<String>)> {
+        let session = self
             .download_sessions
-            .get_async(&id)
+            .read_async(&id, |_, v| v.clone())
             .await
-            .ok_or(anyhow!("download id not found"))?;
-
-        entry
-            .get_mut()
-            .base
-            .file
-            .seek(SeekFrom::Start(from))
-            .await?;
+            .filter(|v| to <= v.size && from < to)
+            .ok_or(anyhow!("invalid download file id or invalid range"))?;
+
+        let mut info = session.info.lock().await;
+        // closed while waiting for the previous read
+        let info = info.as_mut().ok_or(anyhow!("download id not found"))?;
+        info.base.file.seek(SeekFrom::Start(from)).await?;
         let mut buf = vec![0; (to - from) as usize];
-        entry.get_mut().base.file.read_buf(&mut buf).await?;
-        Ok(Self::bytes_to_string_data(buf))
+        info.base.file.read_exact(&mut buf).await?;
+        let rolling_sha1 = info.rolling_sha1.update(from, &buf);
+        Ok((buf, rolling_sha1))
     }
 
+    /// close the file once the reads in flight are done
     pub async fn download_close(&self, id: Uuid) -> anyhow::Result<()> {
-        if self.download_sessions.remove_async(&id).await.is_none() {
+        let Some((_, session)) = self.download_sessions.remove_async(&id).await else {
             bail!("download id not found")
-        }
+        };
+        session.info.lock().await.take();
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// encode bytes the way clients send upload chunks (big endian utf16)
+    fn to_chunk_data(bytes: &[u8]) -> String {
+        String::from_utf16(
+            &bytes
+                .chunks(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect::<Vec<u16>>(),
+        )
+        .unwrap()
+    }
+
+    fn sha1_hex(bytes: &[u8]) -> String {
+        format!("{:x}", Sha1::digest(bytes))
+    }
+
+    /// a unique directory under ROOT, removed by the caller
+    fn test_dir() -> String {
+        let dir = format!("{}/test-{}", ROOT, Uuid::new_v4());
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// files whose upload temp dir lives inside `dir`
+    fn test_files(dir: &str) -> Files {
+        let mut config = ProtocolConfig::default();
+        config.v1.upload_temp_dir = format!("{}/tmp", dir);
+        // allow tiny chunks to keep fixtures short
+        config.v1.min_chunk_size = 1;
+        Files::new(config)
+    }
+
+    #[tokio::test]
+    async fn corrupted_chunk_is_rejected_early() {
+        let dir = test_dir();
+        let path = format!("{}/file.bin", dir);
+        let files = test_files(&dir);
+        let content = b"abcdef";
+
+        let file_id = files
+            .upload_request(Some(&path), 6, 2, Some(&sha1_hex(content)))
+            .await
+            .unwrap();
+
+        let (done, received, rolling) = files
+            .upload_chunk(file_id, 0, to_chunk_data(b"ab"), Some(&sha1_hex(b"ab")))
+            .await
+            .unwrap();
+        assert_eq!((done, received), (false, 2));
+        assert_eq!(rolling, Some(sha1_hex(b"ab")));
+
+        // corrupted middle chunk: claimed checksum is for "cd"
+        let corrupted = files
+            .upload_chunk(file_id, 2, to_chunk_data(b"cx"), Some(&sha1_hex(b"cd")))
+            .await;
+        assert!(corrupted.is_err());
+
+        let (done, _, rolling) = files
+            .upload_chunk(file_id, 2, to_chunk_data(b"cd"), Some(&sha1_hex(b"cd")))
+            .await
+            .unwrap();
+        assert!(!done);
+        assert_eq!(rolling, Some(sha1_hex(b"abcd")));
+
+        let (done, _, rolling) = files
+            .upload_chunk(file_id, 4, to_chunk_data(b"ef"), None)
+            .await
+            .unwrap();
+        assert!(done);
+        assert_eq!(rolling, Some(sha1_hex(content)));
+        assert_eq!(std::fs::read(&path).unwrap(), content);
+        assert!(!files.has_session(&file_id));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn upload_tmp_file_lands_in_temp_dir() {
+        let dir = test_dir();
+        let path = format!("{}/file.bin", dir);
+        let files = test_files(&dir);
+
+        let file_id = files.upload_request(Some(&path), 2, 2, None).await.unwrap();
+        let tmp_path = format!("{}/tmp/{}.tmp", dir, file_id);
+        assert!(Path::new(&tmp_path).exists());
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+
+        let (done, _, _) = files
+            .upload_chunk(file_id, 0, to_chunk_data(b"ab"), None)
+            .await
+            .unwrap();
+        assert!(done);
+        assert!(!Path::new(&tmp_path).exists());
+        assert_eq!(std::fs::read(&path).unwrap(), b"ab");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn upload_empty_file() {
+        let dir = test_dir();
+        let path = format!("{}/empty.bin", dir);
+        let files = test_files(&dir);
+
+        let file_id = files
+            .upload_request(Some(&path), 0, 1 << 20, Some(&sha1_hex(b"")))
+            .await
+            .unwrap();
+        let (done, _, _) = files
+            .upload_chunk(file_id, 0, String::new(), None)
+            .await
+            .unwrap();
+        assert!(done);
+        assert_eq!(std::fs::read(&path).unwrap(), b"");
+        assert!(!files.has_session(&file_id));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn full_disk_cancels_upload() {
+        let dir = test_dir();
+        let path = format!("{}/file.bin", dir);
+        let files = test_files(&dir);
+
+        let file_id = files.upload_request(Some(&path), 2, 2, None).await.unwrap();
+        let tmp_path = format!("{}/tmp/{}.tmp", dir, file_id);
+        // what a write returns with no space left on the device
+        let enospc = std::io::Error::from(ErrorKind::StorageFull);
+        let err = files.fail_upload(file_id, enospc.into()).await;
+
+        assert_eq!(err.downcast_ref::<DiskError>(), Some(&DiskError::Full));
+        assert!(!Path::new(&tmp_path).exists());
+        assert!(!files.upload_cancel(file_id).await);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn other_write_errors_keep_upload() {
+        let dir = test_dir();
+        let path = format!("{}/file.bin", dir);
+        let files = test_files(&dir);
+
+        let file_id = files.upload_request(Some(&path), 2, 2, None).await.unwrap();
+        let err = files
+            .fail_upload(file_id, std::io::Error::from(ErrorKind::Interrupted).into())
+            .await;
+
+        assert!(err.downcast_ref::<DiskError>().is_none());
+        assert!(files.upload_cancel(file_id).await);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn uploaded_file_gets_configured_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_dir();
+        let path = format!("{}/file.bin", dir);
+        let mut files = test_files(&dir);
+        files.protocol_config.v1.file_modes.file = crate::storage::mode::Mode(0o604);
+
+        let file_id = files.upload_request(Some(&path), 2, 2, None).await.unwrap();
+        files
+            .upload_chunk(file_id, 0, to_chunk_data(b"ab"), None)
+            .await
+            .unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o604);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_digest_sha1_and_sha256() {
+        let dir = test_dir();
+        let path = format!("{}/abc.txt", dir);
+        std::fs::write(&path, b"abc").unwrap();
+        let files = test_files(&dir);
+
+        assert_eq!(
+            files.get_digest(&path, HashAlgo::Sha1).await.unwrap(),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            files.get_digest(&path, HashAlgo::Sha256).await.unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(files
+            .get_digest("../abc.txt", HashAlgo::Sha1)
+            .await
+            .is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stat_reports_meta_and_cached_digests() {
+        let dir = test_dir();
+        let path = format!("{}/.abc.txt", dir);
+        std::fs::write(&path, b"abc").unwrap();
+        let files = test_files(&dir);
+
+        let stat = files.stat(&path).await.unwrap();
+        assert_eq!(stat.meta.size, 3);
+        assert!(stat.meta.modified.is_some());
+        assert!(!stat.meta.readonly);
+        #[cfg(unix)]
+        assert!(stat.meta.hidden);
+        // nothing is hashed just for a stat
+        assert_eq!((stat.sha1, stat.sha256), (None, None));
+
+        let sha1 = files.get_digest(&path, HashAlgo::Sha1).await.unwrap();
+        let stat = files.stat(&path).await.unwrap();
+        assert_eq!((stat.sha1, stat.sha256), (Some(sha1), None));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stat_of_missing_file_is_an_error() {
+        let dir = test_dir();
+        let files = test_files(&dir);
+
+        let err = files.stat(&format!("{}/missing", dir)).await.unwrap_err();
+        assert_eq!(err.to_string(), "file not found");
+        assert!(files.stat(&dir).await.is_err());
+        assert!(files.stat("../abc.txt").await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_digest_is_cached_by_mtime_and_size() {
+        let dir = test_dir();
+        let path = format!("{}/abc.txt", dir);
+        std::fs::write(&path, b"abc").unwrap();
+        let files = test_files(&dir);
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        let first = files.get_digest(&path, HashAlgo::Sha1).await.unwrap();
+
+        // same size and mtime: served from cache even though the content changed
+        std::fs::write(&path, b"xyz").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(
+            files.get_digest(&path, HashAlgo::Sha1).await.unwrap(),
+            first
+        );
+
+        // mtime changed: recomputed
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert_ne!(
+            files.get_digest(&path, HashAlgo::Sha1).await.unwrap(),
+            first
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn download_rolling_sha1_matches_file_sha1() {
+        let dir = test_dir();
+        let path = format!("{}/file.bin", dir);
+        let content = b"0123456789abcdefghij";
+        std::fs::write(&path, content).unwrap();
+        let files = test_files(&dir);
+
+        let (file_id, size, sha1) = files.download_request(&path).await.unwrap();
+        assert_eq!(size, content.len() as u64);
+
+        let mut rolling = None;
+        for from in (0..size).step_by(8) {
+            let to = std::cmp::min(from + 8, size);
+            let (_, sha1) = files.download_range(file_id, from, to).await.unwrap();
+            assert_eq!(sha1, Some(sha1_hex(&content[..to as usize])));
+            rolling = sha1;
+        }
+        assert_eq!(rolling, Some(sha1));
+
+        // out of order ranges stop reporting a rolling sha1
+        let (_, sha1) = files.download_range(file_id, 0, 4).await.unwrap();
+        assert_eq!(sha1, None);
+
+        files.download_close(file_id).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn download_close_waits_for_reads_in_flight() {
+        let dir = test_dir();
+        let path = format!("{}/file.bin", dir);
+        std::fs::write(&path, b"abcd").unwrap();
+        let files = Arc::new(test_files(&dir));
+
+        let (file_id, _, _) = files.download_request(&path).await.unwrap();
+        let session = files
+            .download_sessions
+            .read_async(&file_id, |_, v| v.clone())
+            .await
+            .unwrap();
+        // a range read holding the file
+        let reading = session.info.lock().await;
+
+        let close = tokio::spawn({
+            let files = files.clone();
+            async move { files.download_close(file_id).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!close.is_finished());
+        assert!(files.download_range(file_id, 0, 4).await.is_err());
+
+        drop(reading);
+        close.await.unwrap().unwrap();
+        assert!(session.info.lock().await.is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_removes_files() {
+        let dir = test_dir();
+        let path = format!("{}/file.bin", dir);
+        std::fs::write(&path, b"abcd").unwrap();
+        std::fs::create_dir_all(format!("{}/world/region", dir)).unwrap();
+        let files = test_files(&dir);
+
+        files.delete(&path, false).await.unwrap();
+        assert!(!Path::new(&path).exists());
+        assert_eq!(
+            files.delete(&path, false).await.unwrap_err().to_string(),
+            "file not found"
+        );
+
+        let world = format!("{}/world", dir);
+        assert!(files.delete(&world, false).await.is_err());
+        files.delete(&world, true).await.unwrap();
+        assert!(!Path::new(&world).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_refuses_paths_outside_root() {
+        let dir = test_dir();
+        let files = test_files(&dir);
+
+        for path in [
+            "../Cargo.toml",
+            "daemon/../Cargo.toml",
+            "daemon",
+            "daemon/.",
+        ] {
+            assert!(files.delete(path, true).await.is_err(), "{}", path);
+        }
+        assert!(Path::new("Cargo.toml").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_refuses_files_being_downloaded() {
+        let dir = test_dir();
+        let path = format!("{}/file.bin", dir);
+        std::fs::write(&path, b"abcd").unwrap();
+        let files = test_files(&dir);
+
+        let (file_id, _, _) = files.download_request(&path).await.unwrap();
+        assert!(files
+            .delete(&path, false)
+            .await
+            .unwrap_err()
+            .to_string()
+            .contains("in use"));
+        assert!(files.delete(&dir, true).await.is_err());
+        assert!(Path::new(&path).exists());
+
+        files.download_close(file_id).await.unwrap();
+        files.delete(&path, false).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_ignores_downloads_in_sibling_prefixes() {
+        let dir = test_dir();
+        std::fs::create_dir_all(format!("{}/test", dir)).unwrap();
+        std::fs::create_dir_all(format!("{}/test2", dir)).unwrap();
+        let path = format!("{}/test2/x", dir);
+        std::fs::write(&path, b"abcd").unwrap();
+        let files = test_files(&dir);
+
+        let (file_id, _, _) = files.download_request(&path).await.unwrap();
+        files.delete(&format!("{}/test", dir), true).await.unwrap();
+        assert!(files.delete(&format!("{}/test2", dir), true).await.is_err());
+
+        files.download_close(file_id).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn directory_is_listed() {
+        let dir = test_dir();
+        std::fs::create_dir_all(format!("{}/world/region", dir)).unwrap();
+        std::fs::create_dir_all(format!("{}/logs", dir)).unwrap();
+        std::fs::write(format!("{}/server.jar", dir), b"jar").unwrap();
+        std::fs::write(format!("{}/eula.txt", dir), b"eula=true").unwrap();
+        std::fs::write(format!("{}/world/level.dat", dir), b"").unwrap();
+        let files = test_files(&dir);
+
+        let listing = files.list_directory(&dir).await.unwrap();
+        assert_eq!(listing.parent.as_deref(), Some(ROOT));
+        let names =
+            |entries: &[EntryInfo]| entries.iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&listing.files), vec!["eula.txt", "server.jar"]);
+        assert_eq!(names(&listing.directories), vec!["logs", "world"]);
+        assert_eq!(listing.files[1].meta.size, 3);
+
+        let world = files
+            .list_directory(&format!("{}/world/", dir))
+            .await
+            .unwrap();
+        assert_eq!(world.parent, Some(dir.clone()));
+        assert_eq!((world.files.len(), world.directories.len()), (1, 1));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn root_and_invalid_directories() {
+        let dir = test_dir();
+        let files = test_files(&dir);
+
+        assert_eq!(files.list_directory("").await.unwrap().parent, None);
+        assert_eq!(files.list_directory(ROOT).await.unwrap().parent, None);
+        assert!(files.list_directory("..").await.is_err());
+        assert!(files
+            .list_directory(&format!("{}/missing", dir))
+            .await
+            .is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn clean_transfer_leaves_no_sessions() {
+        let dir = test_dir();
+        let path = format!("{}/file.bin", dir);
+        let files = test_files(&dir);
+
+        let file_id = files.upload_request(Some(&path), 4, 4, None).await.unwrap();
+        assert_eq!(files.session_stats(), (1, 0));
+        files
+            .upload_chunk(file_id, 0, to_chunk_data(b"abcd"), None)
+            .await
+            .unwrap();
+
+        let (file_id, _, _) = files.download_request(&path).await.unwrap();
+        assert_eq!(files.session_stats(), (0, 1));
+        files.download_range(file_id, 0, 4).await.unwrap();
+        files.download_close(file_id).await.unwrap();
+
+        assert_eq!(files.session_stats(), (0, 0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn download_sessions_per_file_are_capped() {
+        let dir = test_dir();
+        let path = format!("{}/file.bin", dir);
+        std::fs::write(&path, b"abcd").unwrap();
+        let files = test_files(&dir);
+        let max = files.protocol_config.v1.file_download_sessions;
+
+        for _ in 0..max {
+            files.download_request(&path).await.unwrap();
+        }
+        let download = files.download_request(&path).await;
+        assert!(download
+            .unwrap_err()
+            .to_string()
+            .contains("max download sessions"));
+        assert_eq!(files.session_stats(), (0, max as usize));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sessions_past_global_cap_are_rejected() {
+        let dir = test_dir();
+        let mut config = test_files(&dir).protocol_config;
+        config.max_file_sessions = 2;
+        let files = Files::new(config);
+
+        let path = format!("{}/file.bin", dir);
+        std::fs::write(&path, b"abcd").unwrap();
+        files.download_request(&path).await.unwrap();
+        let upload_path = format!("{}/upload.bin", dir);
+        files
+            .upload_request(Some(&upload_path), 4, 4, None)
+            .await
+            .unwrap();
+
+        let download = files.download_request(&path).await;
+        assert!(download
+            .unwrap_err()
+            .to_string()
+            .contains("too many sessions"));
+        let upload = files
+            .upload_request(Some(&format!("{}/other.bin", dir)), 4, 4, None)
+            .await;
+        assert!(upload
+            .unwrap_err()
+            .to_string()
+            .contains("too many sessions"));
+        assert_eq!(files.session_stats(), (1, 1));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn upload_request_rejects_insane_sizes() {
+        let dir = test_dir();
+        let path = format!("{}/file.bin", dir);
+        let files = Files::new(ProtocolConfig::default());
+
+        let zero_chunk = files.upload_request(Some(&path), 4096, 0, None).await;
+        assert!(zero_chunk.is_err());
+
+        let oversized = files
+            .upload_request(Some(&path), u64::MAX, 1 << 20, None)
+            .await;
+        assert!(oversized.unwrap_err().to_string().contains("too large"));
+
+        let tiny_chunks = files.upload_request(Some(&path), 4096, 2, None).await;
+        assert!(tiny_chunks.is_err());
+
+        // nothing was created for rejected requests
+        assert!(!Path::new(&path).exists());
+        assert_eq!(files.session_stats(), (0, 0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
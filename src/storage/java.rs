@@ -1,19 +1,21 @@
 use regex::Regex;
-use serde::Serialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::ffi::OsStr;
 use std::iter::Iterator;
-use std::path::{absolute, Path};
+use std::path::{absolute, Path, PathBuf};
 use std::process::Output;
 use std::string::ToString;
 use std::sync::{Arc, LazyLock};
+use std::time::Duration;
 use tokio::sync::Mutex;
 
 use anyhow::anyhow;
 use log::{debug, trace, warn};
 use tokio::process::Command;
 use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
 
 use crate::utils::AsyncFetchable;
 
@@ -126,29 +128,74 @@ const EXCLUDED_KEYS: [&str; 5] = ["$", "{", "}", "__", "office"];
 static USER_NAME: LazyLock<String> = LazyLock::new(get_user_name);
 static JAVA_VERSION_REGEX: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"(\d+)(?:\.(\d+))?(?:\.(\d+))?(?:[._](\d+))?(?:-(.+))?").unwrap());
+static QUOTED_VERSION_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"version "([^"]+)""#).unwrap());
+/// `os.arch` as printed by `-XshowSettings:properties`
+static OS_ARCH_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^\s+os\.arch = (\S+)\s*$").unwrap());
+
+/// (marker in the build or vm name, arch), checked before the 64-Bit guess
+const ARCH_MARKERS: [(&str, &str); 3] = [
+    ("aarch64", "aarch64"),
+    ("arm64", "aarch64"),
+    ("ppc64le", "ppc64le"),
+];
+
+/// (marker in `java -version` output, vendor name), checked in order
+const VENDOR_MARKERS: [(&str, &str); 12] = [
+    ("GraalVM", "GraalVM"),
+    ("Temurin", "Eclipse Temurin"),
+    ("AdoptOpenJDK", "AdoptOpenJDK"),
+    ("Zulu", "Azul Zulu"),
+    ("Corretto", "Amazon Corretto"),
+    ("Dragonwell", "Alibaba Dragonwell"),
+    ("BellSoft", "BellSoft Liberica"),
+    ("Microsoft", "Microsoft"),
+    ("Semeru", "IBM Semeru"),
+    ("SapMachine", "SapMachine"),
+    ("JBR", "JetBrains"),
+    ("Java(TM)", "Oracle"),
+];
 
 type JoinHandleMap<K, V> = Arc<Mutex<HashMap<K, JoinHandle<anyhow::Result<V>>>>>;
 
+/// used when neither `whoami` nor the environment tells the user name
+const DEFAULT_USER_NAME: &str = "user";
+
 fn get_user_name() -> String {
-    let output = std::process::Command::new("whoami")
+    user_name_from_command("whoami")
+}
+
+/// run `command` (`whoami`), fall back to `$USER`/`$USERNAME` and then [`DEFAULT_USER_NAME`]
+fn user_name_from_command(command: &str) -> String {
+    std::process::Command::new(command)
         .output()
-        .unwrap()
-        .stdout;
-    let user = String::from_utf8_lossy(&output)
-        .trim()
-        .split("\\")
-        .map(String::from)
-        .collect::<Vec<_>>()
-        .last()
-        .map(String::from)
-        .unwrap();
-    user
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| {
+            // windows: DOMAIN\user
+            String::from_utf8_lossy(&output.stdout)
+                .trim()
+                .rsplit('\\')
+                .next()
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+        })
+        .or_else(|| env::var("USER").ok())
+        .or_else(|| env::var("USERNAME").ok())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| DEFAULT_USER_NAME.to_string())
 }
 
 pub const JAVA_NAME: &str = "java";
 
-fn scan<P>(path: P, join_handle_map: JoinHandleMap<String, JavaInfo>, recursive: bool)
-where
+fn scan<P>(
+    path: P,
+    join_handle_map: JoinHandleMap<String, JavaInfo>,
+    recursive: bool,
+    timeout: Duration,
+    cancel_token: &CancellationToken,
+) where
     P: AsRef<Path>,
 {
     if path.as_ref().is_file() {
@@ -161,6 +208,9 @@ where
     };
 
     for entry in dir {
+        if cancel_token.is_cancelled() {
+            return;
+        }
         let entry = match entry {
             Ok(e) => e,
             Err(_) => return,
@@ -187,23 +237,16 @@ where
             if file_match {
                 debug!("Found java: {}", abs_path.display());
 
+                // key by the canonical path so symlinks to the same java collapse into one entry,
+                // the reported path stays the one found first
+                let key = std::fs::canonicalize(&abs_path)
+                    .map(|p| p.to_string_lossy().to_string())
+                    .unwrap_or_else(|_| abs_path_str.clone());
+                let mut map_guard = futures::executor::block_on(join_handle_map.lock());
                 // async get java info
-                let mut runner = Command::new(abs_path.as_os_str());
-                runner.arg("-version");
-                #[cfg(windows)]
-                {
-                    runner.creation_flags(0x08000000);
-                    // refer to https://learn.microsoft.com/en-us/windows/win32/procthread/process-creation-flags
-                }
-                let child = runner.output();
-
-                let abs_path_str_ = abs_path_str.clone();
-                let handler = tokio::spawn(async move {
-                    JavaInfo::try_from_path_output(abs_path_str_, child.await?)
+                map_guard.entry(key).or_insert_with(|| {
+                    tokio::spawn(JavaInfo::try_from_path(abs_path_str, timeout))
                 });
-
-                let mut map_guard = futures::executor::block_on(join_handle_map.lock());
-                map_guard.entry(abs_path_str).or_insert(handler);
             }
         } else if EXCLUDED_KEYS
             .iter()
@@ -217,48 +260,262 @@ where
                 || name == *USER_NAME)
         {
             let join_handle_map = join_handle_map.clone();
-            scan(path, join_handle_map, recursive)
+            scan(path, join_handle_map, recursive, timeout, cancel_token)
         }
     }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub struct JavaInfo {
+    /// full version string, e.g. `17.0.8` or `1.8.0_381`
     pub version: String,
+    /// major version, e.g. `17` or `8`, `0` if unknown
+    pub major: u32,
+    pub vendor: String,
+    /// runtime name, e.g. `OpenJDK Runtime Environment Temurin-17.0.8+7`
+    pub runtime: String,
     pub path: String,
     pub arch: String,
 }
 
 impl JavaInfo {
+    /// run `<path> -version` and parse its output, giving up after `timeout`
+    async fn try_from_path(path: String, timeout: Duration) -> anyhow::Result<JavaInfo> {
+        // the properties tell the arch, javas older than 7 don't know the flag
+        let output = Self::run(&path, &["-XshowSettings:properties", "-version"], timeout).await?;
+        if output.status.success() || !Self::rejected_option(&output) {
+            return Self::try_from_path_output(path, output);
+        }
+        let output = Self::run(&path, &["-version"], timeout).await?;
+        Self::try_from_path_output(path, output)
+    }
+
+    /// whether the vm refused to start because of an option it doesn't know
+    fn rejected_option(output: &Output) -> bool {
+        let err = String::from_utf8_lossy(&output.stderr);
+        err.contains("Unrecognized option")
+            || err.contains("Could not create the Java Virtual Machine")
+    }
+
+    async fn run(path: &str, args: &[&str], timeout: Duration) -> anyhow::Result<Output> {
+        let mut runner = Command::new(path);
+        runner.args(args).kill_on_drop(true);
+        #[cfg(windows)]
+        {
+            runner.creation_flags(0x08000000);
+            // refer to https://learn.microsoft.com/en-us/windows/win32/procthread/process-creation-flags
+        }
+        Ok(tokio::time::timeout(timeout, runner.output())
+            .await
+            .map_err(|_| anyhow!("{} -version timed out after {:?}", path, timeout))??)
+    }
+
     fn try_from_path_output(path: String, output: Output) -> anyhow::Result<JavaInfo> {
         if output.status.success() {
             let out = String::from_utf8_lossy(&output.stderr).to_string();
+            Ok(Self::from_version_output(path, &out))
+        } else {
+            Err(anyhow!("Failed to get java version"))
+        }
+    }
+
+    /// parse the stderr of `java -version`, optionally preceded by the properties
+    fn from_version_output(path: String, out: &str) -> JavaInfo {
+        let arch = Self::parse_arch(out);
+        // properties are indented, and would be taken for the runtime or the vendor
+        let out = &out
+            .lines()
+            .filter(|line| !line.starts_with(char::is_whitespace) && *line != "Property settings:")
+            .collect::<Vec<_>>()
+            .join("\n");
 
-            let version = JAVA_VERSION_REGEX
-                .find(&out)
-                .map(|m| m.as_str())
-                .unwrap_or("Unknown")
-                .to_string();
+        let version = QUOTED_VERSION_REGEX
+            .captures(out)
+            .and_then(|c| c.get(1))
+            .or_else(|| JAVA_VERSION_REGEX.find(out))
+            .map(|m| m.as_str())
+            .unwrap_or("Unknown")
+            .to_string();
 
-            let arch = if out.contains("64-Bit") { "x64" } else { "x86" }.to_string();
+        let runtime = out
+            .lines()
+            .find(|line| line.contains("Runtime Environment"))
+            .map(|line| line.split("(build").next().unwrap_or(line).trim())
+            .unwrap_or("Unknown")
+            .to_string();
 
-            Ok(JavaInfo {
-                version,
-                path,
-                arch,
+        let vendor = VENDOR_MARKERS
+            .iter()
+            .find(|(marker, _)| out.contains(marker))
+            .map(|(_, vendor)| *vendor)
+            .unwrap_or(if out.contains("OpenJDK") {
+                "OpenJDK"
+            } else {
+                "Unknown"
             })
-        } else {
-            Err(anyhow!("Failed to get java version"))
+            .to_string();
+
+        JavaInfo {
+            major: Self::parse_major(&version),
+            version,
+            vendor,
+            runtime,
+            path,
+            arch,
         }
     }
+
+    /// `os.arch` if printed, else an arch marker in the build or vm names,
+    /// else `x64` for 64-bit vms and `x86` otherwise
+    fn parse_arch(out: &str) -> String {
+        if let Some(os_arch) = OS_ARCH_REGEX.captures(out).map(|c| c[1].to_string()) {
+            return match os_arch.as_str() {
+                "amd64" | "x86_64" => "x64".to_string(),
+                "x86" | "i386" | "i486" | "i586" | "i686" => "x86".to_string(),
+                "arm64" => "aarch64".to_string(),
+                _ => os_arch,
+            };
+        }
+        ARCH_MARKERS
+            .iter()
+            .find(|(marker, _)| out.contains(marker))
+            .map(|(_, arch)| *arch)
+            .unwrap_or(if out.contains("64-Bit") { "x64" } else { "x86" })
+            .to_string()
+    }
+
+    /// `1.8.0_381` -> 8, `17.0.8` -> 17, `21` -> 21
+    fn parse_major(version: &str) -> u32 {
+        let mut parts = version.split(['.', '_', '-', '+']);
+        match parts.next().and_then(|p| p.parse().ok()) {
+            Some(1) => parts.next().and_then(|p| p.parse().ok()).unwrap_or(1),
+            Some(major) => major,
+            None => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JavaConfig {
+    /// when not empty, only these javas are reported and disk scanning is skipped
+    pub java_paths: Vec<PathBuf>,
+    /// when not empty, only these directories are scanned instead of PATH and the disks
+    pub java_scan_roots: Vec<PathBuf>,
+    /// javas not answering `-version` within this are skipped
+    pub version_timeout_secs: u64,
 }
 
-pub async fn java_scan() -> Vec<JavaInfo> {
+impl Default for JavaConfig {
+    fn default() -> Self {
+        Self {
+            java_paths: vec![],
+            java_scan_roots: vec![],
+            version_timeout_secs: 5,
+        }
+    }
+}
+
+impl JavaConfig {
+    fn version_timeout(&self) -> Duration {
+        Duration::from_secs(self.version_timeout_secs)
+    }
+}
+
+/// get java list according to config: pinned `java_paths` if any, otherwise a full scan
+pub async fn java_list(config: &JavaConfig, cancel_token: &CancellationToken) -> Vec<JavaInfo> {
+    if config.java_paths.is_empty() {
+        java_scan(
+            &config.java_scan_roots,
+            config.version_timeout(),
+            cancel_token,
+        )
+        .await
+    } else {
+        java_check(&config.java_paths, config.version_timeout()).await
+    }
+}
+
+/// validate the given javas via `-version`, skip the invalid ones
+async fn java_check(paths: &[PathBuf], timeout: Duration) -> Vec<JavaInfo> {
+    let mut task_set = JoinSet::new();
+    for path in paths {
+        task_set.spawn(JavaInfo::try_from_path(
+            path.to_string_lossy().to_string(),
+            timeout,
+        ));
+    }
+
+    let mut rv = vec![];
+    while let Some(info) = task_set.join_next().await {
+        match info {
+            Ok(Ok(info)) => rv.push(info),
+            Ok(Err(err)) => warn!("{:?}", err),
+            Err(err) => warn!("{:?}", err),
+        }
+    }
+    dedup_java_list(rv)
+}
+
+/// scan javas in `roots`, or in PATH and disks if no roots are given,
+/// returns early with partial results once `cancel_token` is cancelled
+pub async fn java_scan(
+    roots: &[PathBuf],
+    timeout: Duration,
+    cancel_token: &CancellationToken,
+) -> Vec<JavaInfo> {
     let join_handle_map = Arc::new(Mutex::new(HashMap::new()));
+    let mut task_set = JoinSet::new();
+
+    if !roots.is_empty() {
+        for root in roots {
+            trace!("scan root: {}", root.display());
+            let root = root.clone();
+            let join_handle_map = join_handle_map.clone();
+            let cancel_token = cancel_token.clone();
+
+            // add scan task
+            task_set
+                .spawn_blocking(move || scan(root, join_handle_map, true, timeout, &cancel_token));
+        }
+    } else {
+        scan_default_roots(&mut task_set, &join_handle_map, timeout, cancel_token);
+    }
+
+    // wait all scan tasks and then wait all join handles for result
+    tokio::select! {
+        _ = async { while task_set.join_next().await.is_some() {} } => {}
+        _ = cancel_token.cancelled() => debug!("java scan cancelled"),
+    }
+
+    let mut rv = vec![];
+    let mut map_guard = join_handle_map.lock().await;
+    for (_, handle) in map_guard.drain() {
+        if cancel_token.is_cancelled() && !handle.is_finished() {
+            handle.abort();
+            continue;
+        }
+        if let Ok(info) = handle.await {
+            match info {
+                Ok(info) => rv.push(info),
+                Err(ref err) => {
+                    warn!("{:?}", err)
+                }
+            }
+        }
+    }
+    dedup_java_list(rv)
+}
 
+/// scan PATH and every disk (`/` on unix)
+fn scan_default_roots(
+    task_set: &mut JoinSet<()>,
+    join_handle_map: &JoinHandleMap<String, JavaInfo>,
+    timeout: Duration,
+    cancel_token: &CancellationToken,
+) {
     trace!("start scan PATH");
 
-    let mut task_set = JoinSet::new();
     // scan PATH
     if let Some(paths) = env::var_os("PATH") {
         for path in env::split_paths(&paths) {
@@ -266,9 +523,11 @@ pub async fn java_scan() -> Vec<JavaInfo> {
 
             trace!("scan path: {}", path_str);
             let join_handle_map = join_handle_map.clone();
+            let cancel_token = cancel_token.clone();
 
             // add scan task
-            task_set.spawn_blocking(move || scan(path, join_handle_map, true));
+            task_set
+                .spawn_blocking(move || scan(path, join_handle_map, true, timeout, &cancel_token));
         }
     }
     // scan disk
@@ -278,10 +537,11 @@ pub async fn java_scan() -> Vec<JavaInfo> {
             let disk_path = format!("{}:\\", disk);
             if fs::metadata(&disk_path).is_ok() {
                 let join_handle_map = join_handle_map.clone();
+                let cancel_token = cancel_token.clone();
                 // add scan task
                 task_set.spawn_blocking(move || {
                     let path = Path::new(&disk_path);
-                    scan(path, join_handle_map, true)
+                    scan(path, join_handle_map, true, timeout, &cancel_token)
                 });
             }
         }
@@ -290,30 +550,356 @@ pub async fn java_scan() -> Vec<JavaInfo> {
     {
         let path = Path::new("/");
         let join_handle_map = join_handle_map.clone();
+        let cancel_token = cancel_token.clone();
         // add scan task
-        task_set.spawn_blocking(move || scan(path, join_handle_map, true));
+        task_set.spawn_blocking(move || scan(path, join_handle_map, true, timeout, &cancel_token));
     }
+}
 
-    // wait all scan tasks and then wait all join handles for result
-    while task_set.join_next().await.is_some() {}
+/// remove duplicated javas (same canonical path, or same version and arch),
+/// and sort by major version descending
+fn dedup_java_list(mut list: Vec<JavaInfo>) -> Vec<JavaInfo> {
+    list.sort_by(|a, b| {
+        b.major
+            .cmp(&a.major)
+            .then_with(|| b.version.cmp(&a.version))
+            .then_with(|| a.path.cmp(&b.path))
+    });
 
-    let mut rv = vec![];
-    let mut map_guard = join_handle_map.lock().await;
-    for (_, handle) in map_guard.drain() {
-        if let Ok(info) = handle.await {
-            match info {
-                Ok(info) => rv.push(info),
-                Err(ref err) => {
-                    warn!("{:?}", err)
-                }
-            }
-        }
-    }
-    rv
+    let mut seen_paths = HashSet::new();
+    let mut seen_versions = HashSet::new();
+    list.retain(|info| {
+        let canonical =
+            std::fs::canonicalize(&info.path).unwrap_or_else(|_| PathBuf::from(&info.path));
+        seen_paths.insert(canonical)
+            && seen_versions.insert((info.version.clone(), info.arch.clone()))
+    });
+    list
 }
 
 impl AsyncFetchable for Vec<JavaInfo> {
-    async fn fetch() -> Self {
-        java_scan().await
+    type Context = JavaConfig;
+
+    /// partial results of a cancelled scan are dropped
+    async fn fetch(config: &JavaConfig, cancel_token: &CancellationToken) -> Option<Self> {
+        let list = java_list(config, cancel_token).await;
+        (!cancel_token.is_cancelled()).then_some(list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMURIN_17: &str = r#"openjdk version "17.0.8" 2023-07-18
+OpenJDK Runtime Environment Temurin-17.0.8+7 (build 17.0.8+7)
+OpenJDK 64-Bit Server VM Temurin-17.0.8+7 (build 17.0.8+7, mixed mode, sharing)
+"#;
+
+    const ORACLE_8_X86: &str = r#"java version "1.8.0_381"
+Java(TM) SE Runtime Environment (build 1.8.0_381-b09)
+Java HotSpot(TM) Client VM (build 25.381-b09, mixed mode, sharing)
+"#;
+
+    const ZULU_21: &str = r#"openjdk version "21.0.1" 2023-10-17 LTS
+OpenJDK Runtime Environment Zulu21.30+15-CA (build 21.0.1+12-LTS)
+OpenJDK 64-Bit Server VM Zulu21.30+15-CA (build 21.0.1+12-LTS, mixed mode, sharing)
+"#;
+
+    const GRAALVM_21: &str = r#"java version "21.0.1" 2023-10-17
+Java(TM) SE Runtime Environment Oracle GraalVM 21.0.1+12.1 (build 21.0.1+12-jvmci-23.1-b19)
+Java HotSpot(TM) 64-Bit Server VM Oracle GraalVM 21.0.1+12.1 (build 21.0.1+12-jvmci-23.1-b19, mixed mode, sharing)
+"#;
+
+    const TEMURIN_21_AARCH64: &str = r#"openjdk version "21.0.1" 2023-10-17 LTS
+OpenJDK Runtime Environment Temurin-21.0.1+12 (build 21.0.1+12-LTS-aarch64)
+OpenJDK 64-Bit Server VM Temurin-21.0.1+12 (build 21.0.1+12-LTS-aarch64, mixed mode)
+"#;
+
+    /// `-XshowSettings:properties -version` of temurin on apple silicon, shortened
+    const TEMURIN_21_MACOS_PROPERTIES: &str = r#"Property settings:
+    file.encoding = UTF-8
+    java.home = /Library/Java/JavaVirtualMachines/temurin-21.jdk/Contents/Home
+    java.runtime.name = OpenJDK Runtime Environment
+    java.vendor = Eclipse Adoptium
+    java.version = 21.0.1
+    os.arch = aarch64
+    os.name = Mac OS X
+    os.version = 14.1
+
+openjdk version "21.0.1" 2023-10-17 LTS
+OpenJDK Runtime Environment Temurin-21.0.1+12 (build 21.0.1+12-LTS)
+OpenJDK 64-Bit Server VM Temurin-21.0.1+12 (build 21.0.1+12-LTS, mixed mode)
+"#;
+
+    /// `-XshowSettings:properties -version` of temurin on x86_64 linux, shortened
+    const TEMURIN_17_X64_PROPERTIES: &str = r#"Property settings:
+    java.home = /usr/lib/jvm/temurin-17-jdk-amd64
+    java.runtime.name = OpenJDK Runtime Environment
+    java.vendor = Eclipse Adoptium
+    os.arch = amd64
+    os.name = Linux
+    sun.boot.library.path = /usr/lib/jvm/temurin-17-jdk-amd64/lib
+
+openjdk version "17.0.8" 2023-07-18
+OpenJDK Runtime Environment Temurin-17.0.8+7 (build 17.0.8+7)
+OpenJDK 64-Bit Server VM Temurin-17.0.8+7 (build 17.0.8+7, mixed mode, sharing)
+"#;
+
+    #[cfg(unix)]
+    #[test]
+    fn dedup_symlinked_java() {
+        let root = crate::utils::temp_path("mcsl-java");
+        std::fs::create_dir_all(root.join("jdk-17/bin")).unwrap();
+        std::fs::create_dir_all(root.join("jdk-21/bin")).unwrap();
+        let java17 = root.join("jdk-17/bin/java");
+        let java21 = root.join("jdk-21/bin/java");
+        std::fs::write(&java17, "").unwrap();
+        std::fs::write(&java21, "").unwrap();
+        std::os::unix::fs::symlink(&java17, root.join("java")).unwrap();
+
+        let info = |path: &Path, out: &str| {
+            JavaInfo::from_version_output(path.to_string_lossy().to_string(), out)
+        };
+        let list = dedup_java_list(vec![
+            info(&root.join("java"), TEMURIN_17),
+            info(&java17, TEMURIN_17),
+            info(&java21, ZULU_21),
+        ]);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].major, 21);
+        assert_eq!(list[1].major, 17);
+    }
+
+    /// write a fake java printing `stderr` on `-version`
+    #[cfg(unix)]
+    fn fake_java(dir: &Path, stderr: &str) -> PathBuf {
+        script_java(dir, &format!("cat >&2 <<'EOF'\n{}EOF\n", stderr))
+    }
+
+    /// write a fake java running the shell `script`
+    #[cfg(unix)]
+    fn script_java(dir: &Path, script: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::create_dir_all(dir).unwrap();
+        let path = dir.join(JAVA_NAME);
+        std::fs::write(&path, format!("#!/bin/sh\n{}", script)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn pinned_java_paths_bypass_scan() {
+        let root = crate::utils::temp_path("mcsl-java");
+        let java = fake_java(&root.join("bin"), TEMURIN_17);
+        let config = JavaConfig {
+            java_paths: vec![java.clone(), root.join("missing/java")],
+            ..Default::default()
+        };
+
+        let begin = std::time::Instant::now();
+        let list = java_list(&config, &CancellationToken::new()).await;
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert!(begin.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].path, java.to_string_lossy());
+        assert_eq!(list[0].major, 17);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn scan_roots_replace_default_scan() {
+        let root = crate::utils::temp_path("mcsl-java");
+        let java = fake_java(&root, TEMURIN_17);
+
+        let begin = std::time::Instant::now();
+        let list = java_scan(
+            std::slice::from_ref(&root),
+            Duration::from_secs(5),
+            &CancellationToken::new(),
+        )
+        .await;
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert!(begin.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].path, java.to_string_lossy());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn scan_collapses_symlinked_java() {
+        let root = crate::utils::temp_path("mcsl-java");
+        let java = fake_java(&root.join("jdk-17/bin"), TEMURIN_17);
+        std::os::unix::fs::symlink(&java, root.join("java")).unwrap();
+
+        let list = java_scan(
+            std::slice::from_ref(&root),
+            Duration::from_secs(5),
+            &CancellationToken::new(),
+        )
+        .await;
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(list.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hung_java_is_skipped() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = crate::utils::temp_path("mcsl-java");
+        let java = fake_java(&root.join("jdk-17/bin"), TEMURIN_17);
+        let hung = root.join("jdk-hung/bin").join(JAVA_NAME);
+        std::fs::create_dir_all(hung.parent().unwrap()).unwrap();
+        std::fs::write(&hung, "#!/bin/sh\nsleep 10\n").unwrap();
+        std::fs::set_permissions(&hung, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let begin = std::time::Instant::now();
+        let list = java_scan(
+            std::slice::from_ref(&root),
+            Duration::from_millis(500),
+            &CancellationToken::new(),
+        )
+        .await;
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert!(begin.elapsed() < Duration::from_secs(5));
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].path, java.to_string_lossy());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn plain_version_is_only_tried_for_unknown_options() {
+        let root = crate::utils::temp_path("mcsl-java");
+        let timeout = Duration::from_secs(5);
+        let old = script_java(
+            &root.join("old"),
+            &format!(
+                "if [ \"$1\" != -version ]; then\n\
+                 echo \"Unrecognized option: $1\" >&2\n\
+                 echo 'Error: Could not create the Java Virtual Machine.' >&2\n\
+                 exit 1\n\
+                 fi\n\
+                 cat >&2 <<'EOF'\n{}EOF\n",
+                TEMURIN_17
+            ),
+        );
+        let calls = root.join("calls");
+        let broken = script_java(
+            &root.join("broken"),
+            &format!(
+                "echo \"$@\" >> {}\necho 'Error: broken' >&2\nexit 1\n",
+                calls.display()
+            ),
+        );
+
+        let info = JavaInfo::try_from_path(old.to_string_lossy().to_string(), timeout).await;
+        let broken = JavaInfo::try_from_path(broken.to_string_lossy().to_string(), timeout).await;
+        let calls = std::fs::read_to_string(&calls).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(info.unwrap().major, 17);
+        assert!(broken.is_err());
+        assert_eq!(calls.lines().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn cancelled_scan_returns_quickly() {
+        let cancel_token = CancellationToken::new();
+        let canceller = cancel_token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            canceller.cancel();
+        });
+
+        let begin = std::time::Instant::now();
+        java_scan(&[], Duration::from_secs(5), &cancel_token).await;
+        assert!(begin.elapsed() < std::time::Duration::from_secs(1));
+
+        // already cancelled: nothing is scanned
+        assert!(java_scan(&[], Duration::from_secs(5), &cancel_token)
+            .await
+            .is_empty());
+    }
+
+    #[test]
+    fn missing_whoami_does_not_panic() {
+        let name = user_name_from_command("mcsl-daemon-missing-whoami");
+        assert!(!name.is_empty());
+    }
+
+    fn parse(out: &str) -> JavaInfo {
+        JavaInfo::from_version_output("java".to_string(), out)
+    }
+
+    #[test]
+    fn parse_temurin() {
+        let info = parse(TEMURIN_17);
+        assert_eq!(info.version, "17.0.8");
+        assert_eq!(info.major, 17);
+        assert_eq!(info.vendor, "Eclipse Temurin");
+        assert_eq!(info.runtime, "OpenJDK Runtime Environment Temurin-17.0.8+7");
+        assert_eq!(info.arch, "x64");
+    }
+
+    #[test]
+    fn parse_oracle_legacy_version() {
+        let info = parse(ORACLE_8_X86);
+        assert_eq!(info.version, "1.8.0_381");
+        assert_eq!(info.major, 8);
+        assert_eq!(info.vendor, "Oracle");
+        assert_eq!(info.runtime, "Java(TM) SE Runtime Environment");
+        assert_eq!(info.arch, "x86");
+    }
+
+    #[test]
+    fn parse_arm_arch() {
+        let info = parse(TEMURIN_21_AARCH64);
+        assert_eq!(info.arch, "aarch64");
+        assert_eq!(info.major, 21);
+
+        let out = TEMURIN_21_AARCH64.replace("aarch64", "arm64");
+        assert_eq!(parse(&out).arch, "aarch64");
+
+        // no marker: back to the 64-Bit guess
+        assert_eq!(parse(ORACLE_8_X86).arch, "x86");
+        assert_eq!(parse(TEMURIN_17).arch, "x64");
+
+        let out = TEMURIN_17.replace("(build 17.0.8+7)", "(build 17.0.8+7-ppc64le)");
+        assert_eq!(parse(&out).arch, "ppc64le");
+    }
+
+    #[test]
+    fn parse_arch_from_properties() {
+        let info = parse(TEMURIN_21_MACOS_PROPERTIES);
+        assert_eq!(info.arch, "aarch64");
+        assert_eq!(info.version, "21.0.1");
+        assert_eq!(info.vendor, "Eclipse Temurin");
+        assert_eq!(
+            info.runtime,
+            "OpenJDK Runtime Environment Temurin-21.0.1+12"
+        );
+
+        let info = parse(TEMURIN_17_X64_PROPERTIES);
+        assert_eq!(info.arch, "x64");
+        assert_eq!(info, parse(TEMURIN_17));
+    }
+
+    #[test]
+    fn parse_zulu_and_graalvm() {
+        let zulu = parse(ZULU_21);
+        assert_eq!((zulu.major, zulu.vendor.as_str()), (21, "Azul Zulu"));
+
+        let graal = parse(GRAALVM_21);
+        assert_eq!((graal.major, graal.vendor.as_str()), (21, "GraalVM"));
+        assert_eq!(graal.version, "21.0.1");
     }
 }
@@ -1,7 +1,10 @@
 pub use app_config::AppConfig;
-pub use files::Files;
+pub use files::{DiskError, Files};
+pub use mode::FileModes;
 
 pub mod app_config;
+pub mod cleanup;
 pub mod file;
 pub mod files;
 pub mod java;
+pub mod mode;
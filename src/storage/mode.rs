@@ -0,0 +1,98 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// unix permission bits, written as an octal string in the config, e.g. `"0750"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mode(pub u32);
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04o}", self.0)
+    }
+}
+
+impl Serialize for Mode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Mode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mode = String::deserialize(deserializer)?;
+        u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .map(Mode)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid file mode: {}", mode)))
+    }
+}
+
+/// modes of the files and directories the daemon creates, ignored on windows
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct FileModes {
+    /// directories, still masked by the umask of the daemon
+    pub dir: Mode,
+    pub file: Mode,
+}
+
+impl Default for FileModes {
+    fn default() -> Self {
+        Self {
+            dir: Mode(0o750),
+            file: Mode(0o640),
+        }
+    }
+}
+
+impl FileModes {
+    /// `create_dir_all` giving every created directory the dir mode
+    pub async fn create_dir_all<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut builder = tokio::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        builder.mode(self.dir.0);
+        builder.create(path).await
+    }
+
+    /// give a created file the file mode
+    pub async fn apply_to_file<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(self.file.0)).await
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = path;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_are_octal_strings() {
+        let modes: FileModes = serde_json::from_str(r#"{"dir": "0700"}"#).unwrap();
+        assert_eq!(modes.dir, Mode(0o700));
+        assert_eq!(modes.file, Mode(0o640));
+        assert_eq!(
+            serde_json::to_string(&modes).unwrap(),
+            r#"{"dir":"0700","file":"0640"}"#
+        );
+        assert!(serde_json::from_str::<Mode>(r#""0800""#).is_err());
+        assert!(serde_json::from_str::<Mode>(r#""17777""#).is_err());
+    }
+}
@@ -1,5 +1,5 @@
 use std::num::NonZeroU32;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use jsonwebtoken::{decode, encode, errors, DecodingKey, EncodingKey, Header, Validation};
 use ring::pbkdf2::{self, PBKDF2_HMAC_SHA256};
@@ -10,7 +10,8 @@ use crate::utils::{self, base64_decode, base64_encode};
 
 const SALT_LEN: usize = 16;
 const CREDENTIAL_LEN: usize = 32;
-const N_ITER: u32 = 10_000;
+/// rounds of hashes stored as `salt$hash`, before the count became configurable
+const LEGACY_N_ITER: u32 = 10_000;
 pub struct Auth;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -50,19 +51,23 @@ impl JwtClaims {
     }
 
     pub fn extract_usr(token: &str) -> Option<String> {
+        Self::decode_unverified(token).map(|claims| claims.usr)
+    }
+
+    /// when a token stops being accepted, only meaningful once it has been validated
+    pub fn extract_expiry(token: &str) -> Option<SystemTime> {
+        Self::decode_unverified(token).map(|claims| UNIX_EPOCH + Duration::from_secs(claims.exp))
+    }
+
+    fn decode_unverified(token: &str) -> Option<Self> {
         // 跳过校验获取claims
         let parts: Vec<&str> = token.split('.').collect();
         if parts.len() != 3 {
             return None;
         }
-        if let Ok(claims_text) = utils::base64_decode(parts[1]) {
-            if let Ok(claims_json) = std::str::from_utf8(&claims_text) {
-                if let Ok(claims) = serde_json::from_str::<JwtClaims>(claims_json) {
-                    return Some(claims.usr);
-                }
-            }
-        }
-        None
+        let claims_text = utils::base64_decode(parts[1]).ok()?;
+        let claims_json = std::str::from_utf8(&claims_text).ok()?;
+        serde_json::from_str::<JwtClaims>(claims_json).ok()
     }
 }
 
@@ -78,18 +83,35 @@ impl JwtClaims {
 }
 
 impl Auth {
-    pub fn verify_pwd(pwd: &str, pwd_hash: &str) -> bool {
-        let parts: Vec<&str> = pwd_hash.split('$').collect();
-        if parts.len() != 2 {
-            return false;
+    /// rounds of a stored hash, hashes without a rounds field predate the setting
+    pub fn pwd_iterations(pwd_hash: &str) -> Option<u32> {
+        match pwd_hash.split('$').collect::<Vec<_>>()[..] {
+            [_, _] => Some(LEGACY_N_ITER),
+            [n_iter, _, _] => n_iter.parse().ok(),
+            _ => None,
         }
+    }
 
-        let salt = base64_decode(parts[0]).unwrap();
-        let stored_hash = base64_decode(parts[1]).unwrap();
+    pub fn verify_pwd(pwd: &str, pwd_hash: &str) -> bool {
+        let (n_iter, salt, stored_hash) = match pwd_hash.split('$').collect::<Vec<_>>()[..] {
+            [salt, hash] => (LEGACY_N_ITER, salt, hash),
+            [n_iter, salt, hash] => match n_iter.parse() {
+                Ok(n_iter) => (n_iter, salt, hash),
+                Err(_) => return false,
+            },
+            _ => return false,
+        };
+        let (Some(n_iter), Ok(salt), Ok(stored_hash)) = (
+            NonZeroU32::new(n_iter),
+            base64_decode(salt),
+            base64_decode(stored_hash),
+        ) else {
+            return false;
+        };
 
         pbkdf2::verify(
             PBKDF2_HMAC_SHA256,
-            NonZeroU32::new(N_ITER).unwrap(),
+            n_iter,
             &salt,
             pwd.as_bytes(),
             &stored_hash,
@@ -97,8 +119,9 @@ impl Auth {
         .is_ok()
     }
 
-    // 使用Pbkdf2,盐量16,key长32,迭代次数10000，hash算法：sha256
-    pub fn hash_pwd(pwd: &str) -> String {
+    // 使用Pbkdf2,盐量16,key长32,hash算法：sha256, 格式: 迭代次数$盐$hash
+    pub fn hash_pwd(pwd: &str, n_iter: u32) -> String {
+        let n_iter = n_iter.max(1);
         let rng = SystemRandom::new();
         let mut salt = [0u8; SALT_LEN];
         rng.fill(&mut salt).map_err(|e| e.to_string()).unwrap();
@@ -106,7 +129,7 @@ impl Auth {
         let mut pbkdf2_hash = [0u8; CREDENTIAL_LEN];
         pbkdf2::derive(
             PBKDF2_HMAC_SHA256,
-            NonZeroU32::new(N_ITER).unwrap(),
+            NonZeroU32::new(n_iter).unwrap(),
             &salt,
             pwd.as_bytes(),
             &mut pbkdf2_hash,
@@ -114,6 +137,39 @@ impl Auth {
 
         let salt_base64 = base64_encode(&salt);
         let hash_base64 = base64_encode(&pbkdf2_hash);
-        format!("{}${}", salt_base64, hash_base64)
+        format!("{}${}${}", n_iter, salt_base64, hash_base64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry_is_read_from_token() {
+        let token = JwtClaims::new("test".to_string(), 60).to_token("secret");
+        let expiry = JwtClaims::extract_expiry(&token).unwrap();
+        let left = expiry.duration_since(SystemTime::now()).unwrap();
+        assert!(left <= Duration::from_secs(60) && left > Duration::from_secs(55));
+        assert_eq!(JwtClaims::extract_expiry("not.a.token"), None);
+    }
+
+    #[test]
+    fn hashes_record_their_iterations() {
+        for n_iter in [1_000, 2_000] {
+            let hash = Auth::hash_pwd("hunter2", n_iter);
+            assert_eq!(Auth::pwd_iterations(&hash), Some(n_iter));
+            assert!(Auth::verify_pwd("hunter2", &hash));
+            assert!(!Auth::verify_pwd("hunter3", &hash));
+        }
+    }
+
+    #[test]
+    fn legacy_hashes_still_verify() {
+        let hash = Auth::hash_pwd("hunter2", LEGACY_N_ITER);
+        let legacy = hash.split_once('$').unwrap().1;
+        assert_eq!(Auth::pwd_iterations(legacy), Some(LEGACY_N_ITER));
+        assert!(Auth::verify_pwd("hunter2", legacy));
+        assert!(!Auth::verify_pwd("hunter2", "not$base64!$at all"));
     }
 }
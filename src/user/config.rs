@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// pbkdf2 rounds of new password hashes, older hashes are upgraded on the next login
+    pub pbkdf2_iterations: u32,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            pbkdf2_iterations: 10_000,
+        }
+    }
+}
+
+impl AuthConfig {
+    /// the configured rounds, pbkdf2 needs at least one
+    pub fn iterations(&self) -> u32 {
+        self.pbkdf2_iterations.max(1)
+    }
+}
@@ -1,6 +1,10 @@
 pub use auth::JwtClaims;
-pub use users::{Users, UsersManager};
+pub use config::AuthConfig;
+pub use throttle::LoginThrottle;
+pub use users::{User, Users, UsersManager};
 
 mod auth;
+mod config;
+mod throttle;
 pub mod userdb;
 pub mod users;
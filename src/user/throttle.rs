@@ -0,0 +1,125 @@
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use scc::HashMap;
+
+/// failures allowed before a key gets locked out
+const FREE_FAILURES: u32 = 5;
+/// first lockout, doubled on every further failure
+const BASE_LOCKOUT: Duration = Duration::from_secs(1);
+const MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+/// failure records are pruned past this many keys
+const MAX_TRACKED: usize = 10_000;
+
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+impl Failures {
+    fn lockout(&self) -> Option<Duration> {
+        let exp = self.count.checked_sub(FREE_FAILURES)?;
+        Some(
+            BASE_LOCKOUT
+                .saturating_mul(1 << exp.min(20))
+                .min(MAX_LOCKOUT),
+        )
+    }
+
+    /// time left until the next attempt is allowed
+    fn retry_after(&self) -> Option<Duration> {
+        let left = self.lockout()?.checked_sub(self.last.elapsed())?;
+        (!left.is_zero()).then_some(left)
+    }
+}
+
+/// in-memory login attempt throttling per user name and per ip, with exponential lockout
+#[derive(Default)]
+pub struct LoginThrottle {
+    // use ahash to speed up ops
+    failures: HashMap<String, Failures, ahash::RandomState>,
+}
+
+impl LoginThrottle {
+    fn keys(usr: &str, ip: IpAddr) -> [String; 2] {
+        [format!("usr:{}", usr), format!("ip:{}", ip)]
+    }
+
+    /// `Err` with the time to wait if the user or the ip is locked out
+    pub fn check(&self, usr: &str, ip: IpAddr) -> Result<(), Duration> {
+        let retry_after = Self::keys(usr, ip)
+            .iter()
+            .filter_map(|key| self.failures.read(key, |_, f| f.retry_after()).flatten())
+            .max();
+        match retry_after {
+            Some(retry_after) => Err(retry_after),
+            None => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&self, usr: &str, ip: IpAddr) {
+        if self.failures.len() > MAX_TRACKED {
+            self.failures.retain(|_, f| f.last.elapsed() < MAX_LOCKOUT);
+        }
+        for key in Self::keys(usr, ip) {
+            self.failures
+                .entry(key)
+                .and_modify(|f| {
+                    f.count += 1;
+                    f.last = Instant::now();
+                })
+                .or_insert_with(|| Failures {
+                    count: 1,
+                    last: Instant::now(),
+                });
+        }
+    }
+
+    pub fn record_success(&self, usr: &str, ip: IpAddr) {
+        for key in Self::keys(usr, ip) {
+            self.failures.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn repeated_failures_lock_out() {
+        let throttle = LoginThrottle::default();
+        for _ in 0..FREE_FAILURES {
+            assert!(throttle.check("admin", IP).is_ok());
+            throttle.record_failure("admin", IP);
+        }
+        let retry_after = throttle.check("admin", IP).unwrap_err();
+        assert!(retry_after <= BASE_LOCKOUT);
+
+        // the lockout doubles
+        throttle.record_failure("admin", IP);
+        assert!(throttle.check("admin", IP).unwrap_err() > BASE_LOCKOUT);
+
+        // guessing other names from the same ip does not help
+        assert!(throttle.check("root", IP).is_err());
+        let other_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        assert!(throttle.check("root", other_ip).is_ok());
+    }
+
+    #[test]
+    fn success_clears_failures() {
+        let throttle = LoginThrottle::default();
+        for _ in 0..FREE_FAILURES {
+            throttle.record_failure("admin", IP);
+        }
+        assert!(throttle.check("admin", IP).is_err());
+
+        throttle.record_success("admin", IP);
+        assert!(throttle.check("admin", IP).is_ok());
+        throttle.record_failure("admin", IP);
+        assert!(throttle.check("admin", IP).is_ok());
+    }
+}
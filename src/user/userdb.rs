@@ -15,7 +15,7 @@ pub struct UserDb {
     conn: Arc<Mutex<Option<rusqlite::Connection>>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum PermissionGroup {
     Admin,
     User,
@@ -45,10 +45,34 @@ impl ToSql for PermissionGroup {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Permission(String);
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+impl From<&str> for Permission {
+    fn from(permission: &str) -> Self {
+        Permission(permission.to_string())
+    }
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// the permission an action required but the user lacks
+#[derive(Debug, PartialEq, Eq)]
+pub struct MissingPermission(pub Permission);
+
+impl std::fmt::Display for MissingPermission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "missing permission {}", self.0)
+    }
+}
+
+impl std::error::Error for MissingPermission {}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 pub struct Permissions(Vec<Permission>);
 
 impl FromSql for Permissions {
@@ -76,6 +100,89 @@ impl ToSql for Permissions {
     }
 }
 
+impl PermissionGroup {
+    /// permissions every member of the group has on top of its explicit ones
+    pub fn default_permissions(&self) -> Permissions {
+        let permissions: &[&str] = match self {
+            PermissionGroup::Admin => &["**"],
+            // names as required by the v1 `ACTIONS` table
+            PermissionGroup::User => &["instance.status", "file.read", "java.list"],
+            PermissionGroup::Custom => &[],
+        };
+        Permissions(
+            permissions
+                .iter()
+                .map(|p| Permission(p.to_string()))
+                .collect(),
+        )
+    }
+}
+
+/// something a dot separated permission like `instance.start` can be checked against
+pub trait Matchable {
+    fn matches(&self, permission: &str) -> bool;
+}
+
+impl Permission {
+    /// a `!` prefixed permission denies what it matches
+    pub fn is_deny(&self) -> bool {
+        self.0.starts_with('!')
+    }
+}
+
+impl Matchable for Permission {
+    /// `*` matches exactly one segment, `**` matches all remaining ones (at least one).
+    /// the `!` of deny permissions is not part of the pattern
+    fn matches(&self, permission: &str) -> bool {
+        fn match_segments(pattern: &[&str], segments: &[&str]) -> bool {
+            match (pattern.first(), segments.first()) {
+                (Some(&"**"), Some(_)) => true,
+                (Some(p), Some(s)) if *p == "*" || p == s => {
+                    match_segments(&pattern[1..], &segments[1..])
+                }
+                (None, None) => true,
+                _ => false,
+            }
+        }
+        let pattern = self
+            .0
+            .trim_start_matches('!')
+            .split('.')
+            .collect::<Vec<_>>();
+        let segments = permission.split('.').collect::<Vec<_>>();
+        match_segments(&pattern, &segments)
+    }
+}
+
+impl Matchable for Permissions {
+    /// granted by any permission and denied by none, denies win over broader grants
+    fn matches(&self, permission: &str) -> bool {
+        let (denies, grants): (Vec<_>, Vec<_>) = self.0.iter().partition(|p| p.is_deny());
+        !denies.iter().any(|p| p.matches(permission))
+            && grants.iter().any(|p| p.matches(permission))
+    }
+}
+
+impl Permissions {
+    pub fn check(&self, required: &Permission) -> Result<(), MissingPermission> {
+        if self.matches(&required.0) {
+            Ok(())
+        } else {
+            Err(MissingPermission(required.clone()))
+        }
+    }
+
+    /// explicit permissions merged with the defaults of `group`
+    pub fn with_group_defaults(mut self, group: &PermissionGroup) -> Self {
+        for permission in group.default_permissions().0 {
+            if !self.0.contains(&permission) {
+                self.0.push(permission);
+            }
+        }
+        self
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct UserRow {
     pub name: String,
@@ -83,6 +190,31 @@ pub struct UserRow {
     pub password_hash: String,
     pub group: PermissionGroup,
     pub permissions: Permissions,
+    /// unix time, unknown for users created before it was recorded
+    pub created_at: Option<u64>,
+    /// unix time of the last successful authentication
+    pub last_login: Option<u64>,
+}
+
+impl UserRow {
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(UserRow {
+            name: row.get("name")?,
+            secret: row.get("secret")?,
+            password_hash: row.get("password_hash")?,
+            group: row.get("group")?,
+            permissions: row.get("permissions")?,
+            created_at: row.get("created_at")?,
+            last_login: row.get("last_login")?,
+        })
+    }
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
 }
 
 impl UserDb {
@@ -130,34 +262,58 @@ impl UserDb {
 //     }
 // }
 
+/// schema migrations, the n-th one brings the schema from version n to n + 1.
+///
+/// only ever append, released databases may be at any version
+const MIGRATIONS: &[&str] = &[
+    // databases created before versioning already have this table
+    "CREATE TABLE IF NOT EXISTS users(
+        `name` TEXT PRIMARY KEY,
+        `secret` TEXT,
+        `password_hash` TEXT,
+        `group` TEXT,
+        `permissions` TEXT
+    );",
+    "ALTER TABLE users ADD COLUMN `created_at` INTEGER;
+    ALTER TABLE users ADD COLUMN `last_login` INTEGER;",
+];
+
 impl UserDb {
     pub async fn open(&self, db: &str) -> anyhow::Result<()> {
         let conn = rusqlite::Connection::open(db)?;
 
         *self.conn.lock().unwrap() = Some(conn);
 
-        // ensure table
         self.execute_async(|conn| {
             // auto vacuum mode = INCREMENTAL
             conn.pragma_update(None, "auto_vacuum", 1)?;
-
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS users(
-                    `name` TEXT PRIMARY KEY,
-                    `secret` TEXT,
-                    `password_hash` TEXT,
-                    `group` TEXT,
-                    `permissions` TEXT
-                );",
-                [],
-            )?;
-            Ok(())
+            Self::migrate(conn)
         })
         .await?;
 
         Ok(())
     }
 
+    /// bring the schema up to date, the version is kept in `PRAGMA user_version`
+    fn migrate(conn: &mut rusqlite::Connection) -> anyhow::Result<()> {
+        let version: usize = conn.query_row("PRAGMA user_version;", [], |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            bail!(
+                "user database schema version {} is newer than supported {}",
+                version,
+                MIGRATIONS.len()
+            );
+        }
+        for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", idx + 1)?;
+            tx.commit()?;
+            debug!("[UserDb] migrated schema to version {}", idx + 1);
+        }
+        Ok(())
+    }
+
     pub fn close(&self) -> anyhow::Result<()> {
         if let Some(conn) = self.conn.lock().unwrap().take() {
             if let Err((_, e)) = conn.close() {
@@ -167,20 +323,42 @@ impl UserDb {
         Ok(())
     }
 
+    /// close the connection once in-flight queries are done, checkpointing the wal first.
+    ///
+    /// queries issued afterwards fail with "Connection is not open"
+    pub async fn shutdown(&self) -> anyhow::Result<()> {
+        let conn_arc = Arc::clone(&self.conn);
+        tokio::task::spawn_blocking(move || {
+            // queries hold the lock while running, so this waits for them
+            let Some(conn) = conn_arc.lock().unwrap().take() else {
+                return Ok(());
+            };
+            // a no-op unless the database is in wal mode
+            conn.query_row("PRAGMA wal_checkpoint(TRUNCATE);", [], |_| Ok(()))?;
+            if let Err((_, e)) = conn.close() {
+                bail!("Failed to close connection: {}", e);
+            }
+            debug!("[UserDb] closed");
+            Ok(())
+        })
+        .await?
+    }
+
+    /// run a trivial query to check the database is usable
+    pub async fn ping(&self) -> anyhow::Result<()> {
+        self.execute_async(|conn| {
+            conn.query_row("SELECT 1;", [], |_| Ok(()))?;
+            Ok(())
+        })
+        .await
+    }
+
     pub async fn lookup(&self, name: &str) -> Option<UserRow> {
         let name_owned = name.to_string();
 
         let lookup_fn = move |conn: &mut rusqlite::Connection| -> anyhow::Result<UserRow> {
             let mut stmt = conn.prepare("SELECT * FROM users WHERE name = ?;")?;
-            let user = stmt.query_row([name_owned], |row| {
-                Ok(UserRow {
-                    name: row.get(0)?,
-                    secret: row.get(1)?,
-                    password_hash: row.get(2)?,
-                    group: row.get(3)?,
-                    permissions: row.get(4)?,
-                })
-            })?;
+            let user = stmt.query_row([name_owned], UserRow::from_row)?;
             Ok(user)
         };
 
@@ -198,16 +376,7 @@ impl UserDb {
             .execute_async(|conn| {
                 let mut stmt = conn.prepare("SELECT * FROM users;")?;
                 let mut rows = vec![];
-                stmt.query_map([], |row| {
-                    Ok(UserRow {
-                        name: row.get(0)?,
-                        secret: row.get(1)?,
-                        password_hash: row.get(2)?,
-                        group: row.get(3)?,
-                        permissions: row.get(4)?,
-                    })
-                })?
-                .for_each(|row| {
+                stmt.query_map([], UserRow::from_row)?.for_each(|row| {
                     if let Ok(row) = row {
                         rows.push(row);
                     }
@@ -225,8 +394,8 @@ impl UserDb {
     pub async fn insert_row(&self, user: UserRow) -> anyhow::Result<()> {
         self.execute_async(move |conn| {
             conn.execute(
-                "INSERT INTO users (name, secret, password_hash, `group`, permissions) VALUES (?1, ?2, ?3, ?4, ?5);",
-                rusqlite::params![user.name, user.secret, user.password_hash, user.group, user.permissions],
+                "INSERT INTO users (name, secret, password_hash, `group`, permissions, created_at, last_login) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+                rusqlite::params![user.name, user.secret, user.password_hash, user.group, user.permissions, user.created_at, user.last_login],
             )?;
             Ok(())
         })
@@ -247,10 +416,24 @@ impl UserDb {
             password_hash: password_hash.to_string(),
             group: group.clone(),
             permissions: permissions.clone(),
+            created_at: Some(unix_now()),
+            last_login: None,
         };
         self.insert_row(user).await
     }
 
+    pub async fn record_login(&self, name: &str) -> anyhow::Result<()> {
+        let name = name.to_string();
+        self.execute_async(move |conn| {
+            conn.execute(
+                "UPDATE users SET last_login = ?1 WHERE name = ?2;",
+                rusqlite::params![unix_now(), name],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
     pub async fn update(
         &self,
         name: &str,
@@ -298,6 +481,40 @@ impl UserDb {
         Ok(())
     }
 
+    /// insert `rows` in one transaction, with `replace` the table ends up holding exactly `rows`,
+    /// otherwise existing users are kept and the rows naming them skipped. returns the skipped names
+    pub async fn import(&self, rows: Vec<UserRow>, replace: bool) -> anyhow::Result<Vec<String>> {
+        self.execute_async(move |conn| {
+            let tx = conn.transaction()?;
+            if replace {
+                tx.execute("DELETE FROM users;", [])?;
+            }
+            let mut skipped = vec![];
+            {
+                let mut stmt = tx.prepare(
+                    "INSERT OR IGNORE INTO users (name, secret, password_hash, `group`, permissions, created_at, last_login) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+                )?;
+                for user in rows {
+                    let inserted = stmt.execute(rusqlite::params![
+                        user.name,
+                        user.secret,
+                        user.password_hash,
+                        user.group,
+                        user.permissions,
+                        user.created_at,
+                        user.last_login
+                    ])?;
+                    if inserted == 0 {
+                        skipped.push(user.name);
+                    }
+                }
+            }
+            tx.commit()?;
+            Ok(skipped)
+        })
+        .await
+    }
+
     pub async fn remove(&self, name: &str) -> anyhow::Result<()> {
         let name = name.to_string();
         self.execute_async(move |conn| {
@@ -342,3 +559,142 @@ impl Drop for UserDb {
         let _ = self.close();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permissions(list: &[&str]) -> Permissions {
+        Permissions(list.iter().map(|p| Permission(p.to_string())).collect())
+    }
+
+    #[test]
+    fn wildcards_match_segments() {
+        let granted = permissions(&["instance.*.console", "file.**"]);
+        assert!(granted.matches("instance.abc.console"));
+        assert!(!granted.matches("instance.abc.def.console"));
+        assert!(granted.matches("file.read"));
+        assert!(granted.matches("file.upload.chunk"));
+        assert!(!granted.matches("file"));
+        assert!(!granted.matches("java.list"));
+    }
+
+    #[test]
+    fn group_defaults_are_merged() {
+        let admin = Permissions::default().with_group_defaults(&PermissionGroup::Admin);
+        assert!(admin.matches("instance.start"));
+
+        let user = permissions(&["instance.start"]).with_group_defaults(&PermissionGroup::User);
+        assert!(user.matches("instance.start"));
+        assert!(user.matches("file.read"));
+        assert!(!user.matches("file.write"));
+
+        let custom = Permissions::default().with_group_defaults(&PermissionGroup::Custom);
+        assert!(!custom.matches("instance.start"));
+    }
+
+    #[test]
+    fn check_reports_missing_permission() {
+        let granted = permissions(&["file.read"]);
+        assert!(granted.check(&"file.read".into()).is_ok());
+        let missing = granted.check(&"file.write".into()).unwrap_err();
+        assert_eq!(missing.to_string(), "missing permission file.write");
+    }
+
+    #[test]
+    fn deny_wins_over_grant() {
+        let granted = permissions(&["instance.**", "!instance.delete"]);
+        assert!(granted.matches("instance.start"));
+        assert!(!granted.matches("instance.delete"));
+
+        let admin =
+            permissions(&["!instance.*.console"]).with_group_defaults(&PermissionGroup::Admin);
+        assert!(admin.matches("file.write"));
+        assert!(!admin.matches("instance.abc.console"));
+        assert_eq!(
+            admin
+                .check(&"instance.abc.console".into())
+                .unwrap_err()
+                .to_string(),
+            "missing permission instance.abc.console"
+        );
+
+        // a deny alone grants nothing
+        assert!(!permissions(&["!file.write"]).matches("file.read"));
+    }
+
+    #[test]
+    fn segments_are_matched_literally() {
+        assert!(permissions(&["a.*.d"]).matches("a.b-c.d"));
+        assert!(permissions(&["a.**"]).matches("a.b-c.d"));
+        assert!(!permissions(&["a.*"]).matches("a.b-c.d"));
+        assert!(permissions(&["file_2.read"]).matches("file_2.read"));
+        // regex syntax in permissions is plain text
+        assert!(!permissions(&["file.(read|write)"]).matches("file.read"));
+        assert!(!permissions(&["a.b+"]).matches("a.bb"));
+        assert!(!permissions(&["a..b"]).matches("a.b"));
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_in_flight_queries() {
+        let db = UserDb::new();
+        db.open(":memory:").await.unwrap();
+        db.insert(
+            "alice",
+            "secret",
+            "hash",
+            &PermissionGroup::User,
+            &Permissions::default(),
+        )
+        .await
+        .unwrap();
+
+        let lookups = (0..16)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move { db.lookup("alice").await.map(|row| row.name) })
+            })
+            .collect::<Vec<_>>();
+        db.shutdown().await.unwrap();
+
+        // each lookup either ran before the close or failed cleanly after it
+        for lookup in lookups {
+            let name = lookup.await.unwrap();
+            assert!(name.is_none() || name.as_deref() == Some("alice"));
+        }
+        assert!(db.ping().await.is_err());
+        db.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unversioned_database_is_migrated() {
+        let path = crate::utils::temp_path("users").with_extension("db");
+        let path = path.to_str().unwrap();
+        {
+            let conn = rusqlite::Connection::open(path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE users(`name` TEXT PRIMARY KEY, `secret` TEXT, `password_hash` TEXT, `group` TEXT, `permissions` TEXT);
+                INSERT INTO users VALUES ('alice', 'secret', 'hash', 'User', '[]');",
+            )
+            .unwrap();
+        }
+
+        let db = UserDb::new();
+        db.open(path).await.unwrap();
+        let version: usize = db
+            .execute_async(|conn| Ok(conn.query_row("PRAGMA user_version;", [], |row| row.get(0))?))
+            .await
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        let alice = db.lookup("alice").await.unwrap();
+        assert_eq!(alice.secret, "secret");
+        assert_eq!((alice.created_at, alice.last_login), (None, None));
+
+        // reopening a current database is a no-op
+        db.shutdown().await.unwrap();
+        db.open(path).await.unwrap();
+        assert!(db.has_user("alice").await);
+        db.shutdown().await.unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -2,11 +2,12 @@ use std::collections::HashMap;
 
 use crate::user::{
     auth::Auth,
-    userdb::{PermissionGroup, Permissions, UserDb},
+    config::AuthConfig,
+    userdb::{PermissionGroup, Permissions, UserDb, UserRow},
 };
 use crate::utils;
 use anyhow::bail;
-use log::info;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
 use super::JwtClaims;
@@ -31,30 +32,94 @@ pub struct UserMeta {
     pub permissions: Permissions,
 }
 
+/// what admins may see of a user, without secrets
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct UserInfo {
+    pub name: String,
+    pub group: PermissionGroup,
+    pub permissions: Permissions,
+    pub created_at: Option<u64>,
+    pub last_login: Option<u64>,
+}
+
+/// a user as exported for backups, secrets are left out unless asked for
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserRecord {
+    pub name: String,
+    pub group: PermissionGroup,
+    #[serde(default)]
+    pub permissions: Permissions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    /// token secret, a fresh one is generated on import if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<u64>,
+    #[serde(default)]
+    pub last_login: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// keep existing users, skip imported ones with the same name
+    #[default]
+    Merge,
+    /// drop all existing users first
+    Replace,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub usr: String,
     pub meta: UserMeta,
 }
 
+impl User {
+    pub fn is_admin(&self) -> bool {
+        matches!(self.meta.permission_groups, PermissionGroup::Admin)
+    }
+
+    /// a user called `test` with the defaults of `group` and no credentials
+    #[cfg(test)]
+    pub fn test(group: PermissionGroup) -> Self {
+        Self {
+            usr: "test".to_string(),
+            meta: UserMeta {
+                secret: String::new(),
+                pwd_hash: String::new(),
+                permissions: Permissions::default().with_group_defaults(&group),
+                permission_groups: group,
+            },
+        }
+    }
+}
+
 pub struct Users {
     user_db: UserDb,
+    auth_config: AuthConfig,
 }
 
 impl UsersManager for Users {
     async fn auth(&self, usr: &str, pwd: &str) -> Option<UserMeta> {
-        self.user_db.lookup(usr).await.and_then(|user_row| {
+        let meta = self.user_db.lookup(usr).await.and_then(|user_row| {
             if Auth::verify_pwd(pwd, &user_row.password_hash) {
                 Some(UserMeta {
                     secret: user_row.secret,
                     pwd_hash: user_row.password_hash,
+                    permissions: user_row.permissions.with_group_defaults(&user_row.group),
                     permission_groups: user_row.group,
-                    permissions: user_row.permissions,
                 })
             } else {
                 None
             }
-        })
+        });
+        if let Some(meta) = &meta {
+            self.record_login(usr).await;
+            self.upgrade_pwd_hash(usr, pwd, &meta.pwd_hash).await;
+        }
+        meta
     }
 
     async fn auth_token(&self, token: &str) -> Option<User> {
@@ -63,7 +128,7 @@ impl UsersManager for Users {
             let user_query = self.user_db.lookup(&name).await;
             if let Some(secret) = user_query.as_ref().map(|row| &row.secret) {
                 // validate token
-                return JwtClaims::from_token(token, secret)
+                let user = JwtClaims::from_token(token, secret)
                     .ok()
                     .and_then(|claims| {
                         let user_row = user_query.unwrap(); // unwrap is safe
@@ -73,8 +138,10 @@ impl UsersManager for Users {
                                 meta: UserMeta {
                                     secret: user_row.secret,
                                     pwd_hash: user_row.password_hash,
+                                    permissions: user_row
+                                        .permissions
+                                        .with_group_defaults(&user_row.group),
                                     permission_groups: user_row.group,
-                                    permissions: user_row.permissions,
                                 },
                             })
                         } else {
@@ -82,6 +149,10 @@ impl UsersManager for Users {
                             None
                         }
                     });
+                if user.is_some() {
+                    self.record_login(&name).await;
+                }
+                return user;
             }
         }
         None
@@ -122,7 +193,7 @@ impl UsersManager for Users {
             // expire tokens
             self.expire_user_tokens(usr).await?;
             self.user_db
-                .update(usr, None, Some(Auth::hash_pwd(pwd)), None, None)
+                .update(usr, None, Some(self.hash_pwd(pwd)), None, None)
                 .await?;
         } else {
             bail!("User not found")
@@ -165,15 +236,16 @@ impl UsersManager for Users {
 }
 
 impl Users {
-    fn new() -> Self {
+    fn new(auth_config: AuthConfig) -> Self {
         // DashMap 添加了serde feature可以直接序列化反序列化
         Self {
             user_db: UserDb::new(),
+            auth_config,
         }
     }
 
-    pub async fn build(db_path: &'static str) -> anyhow::Result<Self> {
-        let this = Self::new();
+    pub async fn build(db_path: &'static str, auth_config: AuthConfig) -> anyhow::Result<Self> {
+        let this = Self::new(auth_config);
 
         this.user_db.open(db_path).await?;
 
@@ -191,7 +263,7 @@ impl Users {
                 "admin",
                 &UserMeta {
                     secret: utils::get_random_string(16),
-                    pwd_hash: Auth::hash_pwd(&random_pwd),
+                    pwd_hash: self.hash_pwd(&random_pwd),
                     permission_groups: PermissionGroup::Admin,
                     permissions: Permissions::default(),
                 },
@@ -201,6 +273,148 @@ impl Users {
         Ok(())
     }
 
+    /// add a user with a fresh secret, returns the password if one had to be generated
+    pub async fn create_user(
+        &self,
+        usr: &str,
+        group: PermissionGroup,
+        permissions: Permissions,
+        pwd: Option<&str>,
+    ) -> anyhow::Result<Option<String>> {
+        if usr.trim().is_empty() {
+            bail!("User name is empty")
+        }
+        let generated = pwd.is_none().then(|| utils::get_random_string(16));
+        let pwd = pwd.or(generated.as_deref()).unwrap_or_default();
+        self.add_user(
+            usr,
+            &UserMeta {
+                secret: utils::get_random_string(16),
+                pwd_hash: self.hash_pwd(pwd),
+                permission_groups: group,
+                permissions,
+            },
+        )
+        .await?;
+        Ok(generated)
+    }
+
+    pub async fn user_info(&self, usr: &str) -> Option<UserInfo> {
+        self.user_db.lookup(usr).await.map(|row| UserInfo {
+            name: row.name,
+            group: row.group,
+            permissions: row.permissions,
+            created_at: row.created_at,
+            last_login: row.last_login,
+        })
+    }
+
+    fn hash_pwd(&self, pwd: &str) -> String {
+        Auth::hash_pwd(pwd, self.auth_config.iterations())
+    }
+
+    /// rehash with the configured rounds once the plain password is known again,
+    /// a failed update must not fail the login
+    async fn upgrade_pwd_hash(&self, usr: &str, pwd: &str, pwd_hash: &str) {
+        if Auth::pwd_iterations(pwd_hash) == Some(self.auth_config.iterations()) {
+            return;
+        }
+        match self
+            .user_db
+            .update(usr, None, Some(self.hash_pwd(pwd)), None, None)
+            .await
+        {
+            Ok(()) => info!("[Users] upgraded password hash of {}", usr),
+            Err(e) => warn!("[Users] could not upgrade password hash of {}: {}", usr, e),
+        }
+    }
+
+    /// all users, with password hashes and token secrets only if `with_secrets`
+    pub async fn export(&self, with_secrets: bool) -> anyhow::Result<Vec<UserRecord>> {
+        let mut records = self
+            .user_db
+            .user_rows()
+            .await?
+            .into_iter()
+            .map(|row| UserRecord {
+                name: row.name,
+                group: row.group,
+                permissions: row.permissions,
+                password_hash: with_secrets.then_some(row.password_hash),
+                secret: with_secrets.then_some(row.secret),
+                created_at: row.created_at,
+                last_login: row.last_login,
+            })
+            .collect::<Vec<_>>();
+        records.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(records)
+    }
+
+    /// load exported users, nothing is imported if any record is invalid.
+    /// replacing must keep `caller` as an admin, so nobody is locked out.
+    /// returns the names skipped because they already exist
+    pub async fn import(
+        &self,
+        records: Vec<UserRecord>,
+        mode: ImportMode,
+        caller: &str,
+    ) -> anyhow::Result<Vec<String>> {
+        if mode == ImportMode::Replace
+            && !records
+                .iter()
+                .any(|r| r.name == caller && r.group == PermissionGroup::Admin)
+        {
+            bail!("Replacing users must keep {} as an admin", caller)
+        }
+        let mut names = std::collections::HashSet::new();
+        let mut rows = vec![];
+        for record in records {
+            if record.name.trim().is_empty() {
+                bail!("User name is empty")
+            }
+            if !names.insert(record.name.clone()) {
+                bail!("User {} is listed twice", record.name)
+            }
+            let Some(password_hash) = record.password_hash.filter(|h| !h.is_empty()) else {
+                bail!(
+                    "User {} has no password hash, redacted exports can not be imported",
+                    record.name
+                )
+            };
+            if Auth::pwd_iterations(&password_hash).is_none() {
+                bail!("User {} has an invalid password hash", record.name)
+            }
+            rows.push(UserRow {
+                name: record.name,
+                secret: record
+                    .secret
+                    .filter(|s| !s.is_empty())
+                    .unwrap_or_else(|| utils::get_random_string(16)),
+                password_hash,
+                group: record.group,
+                permissions: record.permissions,
+                created_at: record.created_at,
+                last_login: record.last_login,
+            });
+        }
+        self.user_db.import(rows, mode == ImportMode::Replace).await
+    }
+
+    /// a failed update must not fail the login
+    async fn record_login(&self, usr: &str) {
+        if let Err(e) = self.user_db.record_login(usr).await {
+            warn!("[Users] could not record login of {}: {}", usr, e);
+        }
+    }
+
+    pub async fn ping(&self) -> anyhow::Result<()> {
+        self.user_db.ping().await
+    }
+
+    pub async fn shutdown(&self) -> anyhow::Result<()> {
+        self.user_db.shutdown().await
+    }
+
     pub async fn expire_user_tokens(&self, usr: &str) -> anyhow::Result<()> {
         if self.user_db.has_user(usr).await {
             let new_secret = utils::get_random_string(16);
@@ -212,3 +426,200 @@ impl Users {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::userdb::Matchable;
+
+    #[tokio::test]
+    async fn admin_group_grants_permissions() {
+        let users = Users::build(":memory:", AuthConfig::default())
+            .await
+            .unwrap();
+        let pwd = users
+            .create_user("root", PermissionGroup::Admin, Permissions::default(), None)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let meta = users.auth("root", &pwd).await.unwrap();
+        assert!(meta.permissions.matches("instance.start"));
+
+        let token = users.gen_token("root", 60).await.unwrap();
+        let user = users.auth_token(&token).await.unwrap();
+        assert!(user.meta.permissions.matches("instance.start"));
+
+        // defaults are not written back
+        let stored = users.get_user_meta("root").await.unwrap();
+        assert!(!stored.permissions.matches("instance.start"));
+    }
+
+    #[tokio::test]
+    async fn login_updates_last_login() {
+        let users = Users::build(":memory:", AuthConfig::default())
+            .await
+            .unwrap();
+        let pwd = users
+            .create_user("bob", PermissionGroup::User, Permissions::default(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        let info = users.user_info("bob").await.unwrap();
+        assert!(info.created_at.is_some());
+        assert_eq!(info.last_login, None);
+
+        assert!(users.auth("bob", "wrong").await.is_none());
+        assert_eq!(users.user_info("bob").await.unwrap().last_login, None);
+
+        users.auth("bob", &pwd).await.unwrap();
+        let last_login = users.user_info("bob").await.unwrap().last_login;
+        assert!(last_login >= info.created_at);
+    }
+
+    #[tokio::test]
+    async fn outdated_hash_is_upgraded_on_login() {
+        let auth_config = |pbkdf2_iterations| AuthConfig { pbkdf2_iterations };
+        let users = Users::build(":memory:", auth_config(1_000)).await.unwrap();
+        let pwd = users
+            .create_user("bob", PermissionGroup::User, Permissions::default(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        let stored = users.get_user_meta("bob").await.unwrap().pwd_hash;
+        assert_eq!(Auth::pwd_iterations(&stored), Some(1_000));
+
+        let users = Users {
+            auth_config: auth_config(2_000),
+            ..users
+        };
+        assert!(users.auth("bob", "wrong").await.is_none());
+        let stored = users.get_user_meta("bob").await.unwrap().pwd_hash;
+        assert_eq!(Auth::pwd_iterations(&stored), Some(1_000));
+
+        users.auth("bob", &pwd).await.unwrap();
+        let stored = users.get_user_meta("bob").await.unwrap().pwd_hash;
+        assert_eq!(Auth::pwd_iterations(&stored), Some(2_000));
+        users.auth("bob", &pwd).await.unwrap();
+
+        // zero rounds hash with one, which must count as up to date
+        let users = Users {
+            auth_config: auth_config(0),
+            ..users
+        };
+        users.auth("bob", &pwd).await.unwrap();
+        let stored = users.get_user_meta("bob").await.unwrap().pwd_hash;
+        assert_eq!(Auth::pwd_iterations(&stored), Some(1));
+        users.auth("bob", &pwd).await.unwrap();
+        assert_eq!(users.get_user_meta("bob").await.unwrap().pwd_hash, stored);
+    }
+
+    #[tokio::test]
+    async fn exported_users_can_be_imported() {
+        let users = Users::build(":memory:", AuthConfig::default())
+            .await
+            .unwrap();
+        let pwd = users
+            .create_user("bob", PermissionGroup::Admin, Permissions::default(), None)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let redacted = users.export(false).await.unwrap();
+        assert!(redacted
+            .iter()
+            .all(|u| u.password_hash.is_none() && u.secret.is_none()));
+        let backup = users.export(true).await.unwrap();
+
+        let restored = Users::build(":memory:", AuthConfig::default())
+            .await
+            .unwrap();
+        assert!(restored
+            .import(redacted, ImportMode::Replace, "bob")
+            .await
+            .is_err());
+        restored
+            .import(backup.clone(), ImportMode::Replace, "bob")
+            .await
+            .unwrap();
+        assert_eq!(restored.export(true).await.unwrap(), backup);
+        assert!(restored.auth("bob", &pwd).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn merge_keeps_existing_users() {
+        let users = Users::build(":memory:", AuthConfig::default())
+            .await
+            .unwrap();
+        let pwd = users
+            .create_user("bob", PermissionGroup::User, Permissions::default(), None)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let other = Users::build(":memory:", AuthConfig::default())
+            .await
+            .unwrap();
+        for name in ["bob", "carol"] {
+            other
+                .create_user(name, PermissionGroup::Admin, Permissions::default(), None)
+                .await
+                .unwrap();
+        }
+        let skipped = users
+            .import(other.export(true).await.unwrap(), ImportMode::Merge, "bob")
+            .await
+            .unwrap();
+        assert_eq!(skipped, vec!["bob".to_string()]);
+
+        let bob = users.user_info("bob").await.unwrap();
+        assert_eq!(bob.group, PermissionGroup::User);
+        assert!(users.auth("bob", &pwd).await.is_some());
+        assert!(users.user_info("carol").await.is_some());
+
+        // replacing drops the users missing from the import
+        let carol = other.export(true).await.unwrap().split_off(1);
+        users
+            .import(carol, ImportMode::Replace, "carol")
+            .await
+            .unwrap();
+        assert!(users.user_info("bob").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn replace_keeps_the_caller_as_admin() {
+        let users = Users::build(":memory:", AuthConfig::default())
+            .await
+            .unwrap();
+        for (name, group) in [
+            ("bob", PermissionGroup::Admin),
+            ("carol", PermissionGroup::User),
+        ] {
+            users
+                .create_user(name, group, Permissions::default(), None)
+                .await
+                .unwrap();
+        }
+        let backup = users.export(true).await.unwrap();
+
+        assert!(users
+            .import(vec![], ImportMode::Replace, "bob")
+            .await
+            .is_err());
+        assert!(users
+            .import(backup.clone(), ImportMode::Replace, "carol")
+            .await
+            .is_err());
+        assert!(users
+            .import(backup.clone(), ImportMode::Replace, "dave")
+            .await
+            .is_err());
+        assert_eq!(users.export(true).await.unwrap(), backup);
+
+        users
+            .import(backup.clone(), ImportMode::Replace, "bob")
+            .await
+            .unwrap();
+        assert_eq!(users.export(true).await.unwrap(), backup);
+    }
+}
@@ -1,10 +1,15 @@
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 
 // 使用 async trait 方法
 pub trait AsyncFetchable: Clone {
-    async fn fetch() -> Self;
+    /// extra data needed to fetch the value, e.g. config
+    type Context;
+
+    /// `None` if cancelled through `cancel_token`, nothing is cached then
+    async fn fetch(ctx: &Self::Context, cancel_token: &CancellationToken) -> Option<Self>;
 }
 
 #[derive(Clone)]
@@ -20,28 +25,31 @@ where
 pub struct AsyncTimedCache<T: AsyncFetchable> {
     state: Arc<Mutex<TimedCacheState<T>>>,
     duration: Duration,
+    context: Arc<T::Context>,
 }
 
 impl<T: AsyncFetchable> AsyncTimedCache<T> {
-    pub fn new(duration: Duration) -> Self {
+    pub fn new(duration: Duration, context: T::Context) -> Self {
         Self {
             state: Arc::new(Mutex::new(TimedCacheState::None)),
             duration,
+            context: Arc::new(context),
         }
     }
 
-    pub async fn get(&self) -> T {
+    /// the cached value, or a freshly fetched one. `None` if the fetch was cancelled
+    pub async fn get(&self, cancel_token: &CancellationToken) -> Option<T> {
         let mut state_guard = self.state.lock().await;
         match &*state_guard {
             TimedCacheState::Cached((last_modified, value))
                 if last_modified.elapsed() < self.duration =>
             {
-                value.clone()
+                Some(value.clone())
             }
             _ => {
-                let value = T::fetch().await;
+                let value = T::fetch(&self.context, cancel_token).await?;
                 *state_guard = TimedCacheState::Cached((Instant::now(), value.clone()));
-                value
+                Some(value)
             }
         }
     }
@@ -49,22 +57,26 @@ impl<T: AsyncFetchable> AsyncTimedCache<T> {
 
 // 为String类型实现AsyncFetchable特征（示例）
 impl AsyncFetchable for String {
-    async fn fetch() -> Self {
-        tokio::time::sleep(Duration::from_secs(1)).await;
-        "Hello, world!".to_string()
+    type Context = ();
+
+    async fn fetch(_: &(), cancel_token: &CancellationToken) -> Option<Self> {
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(1)) => Some("Hello, world!".to_string()),
+            _ = cancel_token.cancelled() => None,
+        }
     }
 }
 
 #[tokio::test]
 async fn test_async_cache() {
     // create cache
-    let cache = AsyncTimedCache::<String>::new(Duration::from_secs(2));
-    let value = cache.get().await;
+    let cache = AsyncTimedCache::<String>::new(Duration::from_secs(2), ());
+    let value = cache.get(&CancellationToken::new()).await.unwrap();
     assert_eq!(value, "Hello, world!");
 
     // get cached value
     let begin = Instant::now();
-    let value = cache.get().await;
+    let value = cache.get(&CancellationToken::new()).await.unwrap();
     assert_eq!(value, "Hello, world!");
     let elapsed = begin.elapsed();
     assert!(elapsed < Duration::from_millis(1)); // cache hit
@@ -72,7 +84,20 @@ async fn test_async_cache() {
     // sleep for 3 seconds
     tokio::time::sleep(Duration::from_secs(2)).await;
     let begin = Instant::now();
-    let value = cache.get().await;
+    let value = cache.get(&CancellationToken::new()).await.unwrap();
     assert_eq!(value, "Hello, world!");
     assert!(begin.elapsed() >= Duration::from_secs(1)); // cache miss
 }
+
+#[tokio::test]
+async fn cancelled_fetch_is_not_cached() {
+    let cache = AsyncTimedCache::<String>::new(Duration::from_secs(60), ());
+    let cancel_token = CancellationToken::new();
+    cancel_token.cancel();
+    assert_eq!(cache.get(&cancel_token).await, None);
+
+    let begin = Instant::now();
+    let value = cache.get(&CancellationToken::new()).await;
+    assert_eq!(value.as_deref(), Some("Hello, world!"));
+    assert!(begin.elapsed() >= Duration::from_secs(1)); // fetched again
+}
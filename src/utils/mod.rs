@@ -1,9 +1,21 @@
 pub use cache::*;
 pub use encoding::*;
 pub use remains::*;
+pub use trace::*;
+#[cfg(feature = "self_restart")]
+pub use restart::*;
 pub use util::*;
+pub use zip::*;
+#[cfg(test)]
+pub use testing::*;
 
 mod cache;
 mod encoding;
 mod remains;
+#[cfg(feature = "self_restart")]
+mod restart;
+#[cfg(test)]
+mod testing;
+mod trace;
 mod util;
+mod zip;
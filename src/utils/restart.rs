@@ -0,0 +1,46 @@
+use std::process::Command;
+
+/// assemble the command re-executing the current daemon binary with the same arguments and
+/// working directory, so that the same `config.json` is loaded again.
+pub fn restart_command() -> anyhow::Result<Command> {
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(std::env::args_os().skip(1))
+        .current_dir(std::env::current_dir()?);
+    Ok(command)
+}
+
+/// replace the current process with a fresh daemon process
+#[cfg(unix)]
+pub fn restart() -> anyhow::Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let err = restart_command()?.exec();
+    Err(err.into())
+}
+
+/// spawn a fresh daemon process and exit the current one
+#[cfg(not(unix))]
+pub fn restart() -> anyhow::Result<()> {
+    restart_command()?.spawn()?;
+    std::process::exit(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restart_command_preserves_exe_args_and_cwd() {
+        let command = restart_command().unwrap();
+        assert_eq!(command.get_program(), std::env::current_exe().unwrap());
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            std::env::args_os().skip(1).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            command.get_current_dir(),
+            Some(std::env::current_dir().unwrap().as_path())
+        );
+    }
+}
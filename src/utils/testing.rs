@@ -0,0 +1,8 @@
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+/// a fresh path under the system temp dir, nothing is created
+pub fn temp_path(prefix: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("{}-{}", prefix, Uuid::new_v4()))
+}
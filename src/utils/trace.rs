@@ -0,0 +1,96 @@
+use std::future::Future;
+
+use log::{Log, Metadata, Record};
+
+tokio::task_local! {
+    static TRACE_ID: String;
+}
+
+/// run `f` with a trace id, every log emitted while polling it carries the id
+pub async fn with_trace_id<F: Future>(trace_id: String, f: F) -> F::Output {
+    TRACE_ID.scope(trace_id, f).await
+}
+
+/// prefix log records with the trace id of the current task, if any
+pub struct TraceLogger<L: Log> {
+    inner: L,
+}
+
+impl<L: Log> TraceLogger<L> {
+    pub fn new(inner: L) -> Self {
+        Self { inner }
+    }
+}
+
+impl<L: Log> Log for TraceLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let traced = TRACE_ID.try_with(|id| {
+            self.inner.log(
+                &Record::builder()
+                    .args(format_args!("[{}] {}", id, record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            )
+        });
+        if traced.is_err() {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct CaptureLogger {
+        lines: Mutex<Vec<String>>,
+    }
+
+    impl Log for &CaptureLogger {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            self.lines.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn log_line(logger: &impl Log, msg: &str) {
+        logger.log(&Record::builder().args(format_args!("{}", msg)).build());
+    }
+
+    #[tokio::test]
+    async fn logs_share_trace_id() {
+        let capture = CaptureLogger::default();
+        let logger = TraceLogger::new(&capture);
+
+        with_trace_id("req-1".to_string(), async {
+            log_line(&logger, "received");
+            tokio::task::yield_now().await;
+            log_line(&logger, "handled");
+        })
+        .await;
+        log_line(&logger, "untraced");
+
+        assert_eq!(
+            *capture.lines.lock().unwrap(),
+            vec!["[req-1] received", "[req-1] handled", "untraced"]
+        );
+    }
+}
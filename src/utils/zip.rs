@@ -0,0 +1,98 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::bail;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+/// read one entry of a zip (or jar) archive, `None` if it has no entry called `name`.
+/// entries larger than `max_size` bytes are an error, the crc is checked while reading
+pub fn read_zip_entry<P: AsRef<Path>>(
+    path: P,
+    name: &str,
+    max_size: u64,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let entry = match archive.by_name(name) {
+        Ok(entry) => entry,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if entry.size() > max_size {
+        bail!("zip entry {} is too large: {} bytes", name, entry.size());
+    }
+
+    // the declared size is not trusted, reading stops past the limit
+    let mut data = Vec::with_capacity(entry.size() as usize);
+    entry.take(max_size + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > max_size {
+        bail!("zip entry {} is larger than {} bytes", name, max_size);
+    }
+    Ok(Some(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    fn archive(name: &str, method: CompressionMethod, data: &[u8]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        let options = SimpleFileOptions::default().compression_method(method);
+        writer.start_file(name, options).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn reads_zip_entries() {
+        let dir = crate::utils::temp_path("mcsl-zip");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server.jar");
+
+        let json = br#"{"id": "1.20.1", "name": "1.20.1", "world_version": 3465}"#.repeat(8);
+        std::fs::write(
+            &path,
+            archive("version.json", CompressionMethod::Deflated, &json),
+        )
+        .unwrap();
+        assert_eq!(
+            read_zip_entry(&path, "version.json", 1 << 20)
+                .unwrap()
+                .unwrap(),
+            json
+        );
+        assert_eq!(
+            read_zip_entry(&path, "missing.json", 1 << 20).unwrap(),
+            None
+        );
+        assert!(read_zip_entry(&path, "version.json", 16).is_err());
+
+        std::fs::write(&path, archive("a.txt", CompressionMethod::Stored, b"plain")).unwrap();
+        assert_eq!(
+            read_zip_entry(&path, "a.txt", 16).unwrap().unwrap(),
+            b"plain"
+        );
+
+        std::fs::write(&path, b"not a zip").unwrap();
+        assert!(read_zip_entry(&path, "a.txt", 16).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_entries_fail_the_crc_check() {
+        let dir = crate::utils::temp_path("mcsl-zip");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server.jar");
+
+        let mut bytes = archive("a.txt", CompressionMethod::Stored, b"plain");
+        let at = bytes.windows(5).position(|w| w == b"plain").unwrap();
+        bytes[at] = b'P';
+        std::fs::write(&path, bytes).unwrap();
+        assert!(read_zip_entry(&path, "a.txt", 16).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}